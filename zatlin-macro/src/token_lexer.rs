@@ -5,8 +5,8 @@ use proc_macro2::{
 use syn::{
     parse::ParseStream,
     Result,
-    Token, parenthesized,
-    Ident, LitStr, LitInt, Error, token::Paren,
+    Token, parenthesized, braced,
+    Ident, LitStr, LitInt, Error, token::{Paren, Brace},
 };
 use quote::quote;
 
@@ -19,6 +19,7 @@ pub(crate) fn convert_zatlin(input: ParseStream) -> Result<TokenStream> {
         if head.peek(Token![%]) {
             input.parse::<Token![%]>()?;
             tokens.push(quote!{ "%" });
+            tokens.extend(convert_generate_header(input)?);
             tokens.push(convert_expression(input)?);
             input.parse::<Token![;]>()?;
             tokens.push(quote!{ ";" });
@@ -46,6 +47,27 @@ pub(crate) fn convert_zatlin(input: ParseStream) -> Result<TokenStream> {
     })
 }
 
+fn convert_generate_header(input: ParseStream) -> Result<Vec<TokenStream>> {
+    let mut tokens = Vec::new();
+
+    if input.peek(Ident) && (input.peek2(Token![=]) || (input.peek2(LitInt) && input.peek3(Token![=]))) {
+        let generate_name = input.parse::<Ident>()?.to_string();
+        tokens.push(quote!{ #generate_name });
+    }
+
+    if input.peek(LitInt) && input.peek2(Token![=]) {
+        let weight = String::from(input.parse::<LitInt>()?.base10_digits());
+        tokens.push(quote!{ #weight });
+    }
+
+    if input.peek(Token![=]) {
+        input.parse::<Token![=]>()?;
+        tokens.push(quote!{ "=" });
+    }
+
+    Ok(tokens)
+}
+
 fn convert_expression(input: ParseStream) -> Result<TokenStream> {
     let mut patterns = Vec::new();
     let mut has_extract = false;
@@ -75,7 +97,7 @@ fn convert_expression(input: ParseStream) -> Result<TokenStream> {
             return Err(Error::new(input.span(), "end of statement in Exclude Pattern"));
         }
 
-        if let Ok(_) = input.parse::<Token![^]>() {
+        if input.parse::<Token![^]>().is_ok() {
             patterns.push(quote!{ "^" });
         }
 
@@ -85,7 +107,7 @@ fn convert_expression(input: ParseStream) -> Result<TokenStream> {
         };
         patterns.push(pattern);
 
-        if let Ok(_) = input.parse::<Token![^]>() {
+        if input.parse::<Token![^]>().is_ok() {
             patterns.push(quote!{ "^" });
         }
 
@@ -97,14 +119,14 @@ fn convert_expression(input: ParseStream) -> Result<TokenStream> {
             } else if head.peek(Token![|]) {
                 input.parse::<Token![|]>()?;
 
-                if let Ok(_) = input.parse::<Token![^]>() {
+                if input.parse::<Token![^]>().is_ok() {
                     patterns.push(quote!{ "^" });
                 }
 
                 patterns.push(quote!{ "|" });
                 patterns.push(convert_pattern(input)?);
 
-                if let Ok(_) = input.parse::<Token![^]>() {
+                if input.parse::<Token![^]>().is_ok() {
                     patterns.push(quote!{ "^" });
                 }
             } else {
@@ -134,6 +156,10 @@ fn convert_pattern(input: ParseStream) -> Result<TokenStream> {
         else if head.peek(Ident) {
             let variable_name = input.parse::<Ident>()?.to_string();
             values.push(quote!{ #variable_name });
+        } else if head.peek(Token![&]) {
+            input.parse::<Token![&]>()?;
+            let reference = "&".to_owned() + input.parse::<LitInt>()?.base10_digits();
+            values.push(quote!{ #reference });
         } else if head.peek(LitStr) {
             let value = "\"".to_owned() + &input.parse::<LitStr>()?.value() + "\"";
             values.push(quote!{ #value })
@@ -144,6 +170,10 @@ fn convert_pattern(input: ParseStream) -> Result<TokenStream> {
             }
             break;
         }
+
+        if let Some(quantifier) = convert_quantifier(input)? {
+            values.push(quantifier);
+        }
     }
 
     if values.is_empty() {
@@ -154,3 +184,24 @@ fn convert_pattern(input: ParseStream) -> Result<TokenStream> {
         })
     }
 }
+
+fn convert_quantifier(input: ParseStream) -> Result<Option<TokenStream>> {
+    if input.peek(Token![?]) {
+        input.parse::<Token![?]>()?;
+        Ok(Some(quote!{ "?" }))
+    } else if input.peek(Brace) {
+        let content;
+        let _ = braced!(content in input);
+        let min = String::from(content.parse::<LitInt>()?.base10_digits());
+
+        if content.is_empty() {
+            Ok(Some(quote!{ "{", #min, "}" }))
+        } else {
+            content.parse::<Token![,]>()?;
+            let max = String::from(content.parse::<LitInt>()?.base10_digits());
+            Ok(Some(quote!{ "{", #min, ",", #max, "}" }))
+        }
+    } else {
+        Ok(None)
+    }
+}
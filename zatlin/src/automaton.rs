@@ -0,0 +1,639 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+use num_bigint::BigUint;
+use rand::prelude::*;
+use regex::Regex;
+use regex_automata::{
+    dfa::{dense, Automaton as _, StartKind},
+    util::start,
+    Anchored,
+    MatchKind,
+};
+
+use crate::error::Error;
+use crate::parser::{Exclude, Expression, Pattern, SegmentsStruct, Value};
+use crate::{GenerateScope, VariableData};
+
+const MAX_STATES: usize = 200_000;
+
+#[derive(Debug, Clone)]
+struct Edge {
+    label: Option<u8>,
+    weight: f64,
+    target: usize,
+}
+
+// An acyclic epsilon-NFA over the bytes of generated words.
+// Every fragment has exactly one start and one accept state, and the weight of
+// a path is the probability of the derivation it stands for.
+#[derive(Debug, Clone)]
+pub(crate) struct Nfa {
+    states: Vec<Vec<Edge>>,
+    start: usize,
+    accept: usize,
+}
+
+impl Nfa {
+    fn literal(text: &str) -> Self {
+        let bytes = text.as_bytes();
+        let states = bytes.iter().enumerate().map(|(index, byte)| vec![Edge { label: Some(*byte), weight: 1.0, target: index + 1 }])
+            .chain(std::iter::once(vec![]))
+            .collect();
+
+        Self { states, start: 0, accept: bytes.len() }
+    }
+
+    fn nothing() -> Self {
+        Self { states: vec![vec![], vec![]], start: 0, accept: 1 }
+    }
+
+    fn embed(&mut self, other: &Nfa) -> (usize, usize) {
+        let offset = self.states.len();
+        self.states.extend(other.states.iter().map(|edges| {
+            edges.iter().map(|x| Edge { label: x.label, weight: x.weight, target: x.target + offset }).collect()
+        }));
+
+        (other.start + offset, other.accept + offset)
+    }
+
+    fn concat(items: &[Nfa]) -> Result<Self, Error> {
+        let mut result = Self::literal("");
+        for item in items.iter() {
+            let (start, accept) = result.embed(item);
+            result.states[result.accept].push(Edge { label: None, weight: 1.0, target: start });
+            result.accept = accept;
+        }
+
+        result.check_size()
+    }
+
+    fn union(items: &[(Nfa, f64)]) -> Result<Self, Error> {
+        let mut result = Self::nothing();
+        for (item, weight) in items.iter() {
+            let (start, accept) = result.embed(item);
+            result.states[result.start].push(Edge { label: None, weight: *weight, target: start });
+            result.states[accept].push(Edge { label: None, weight: 1.0, target: result.accept });
+        }
+
+        result.check_size()
+    }
+
+    fn check_size(self) -> Result<Self, Error> {
+        if self.states.len() > MAX_STATES {
+            Err(Error::ErrorMessage(String::from("Grammar is too large to analyse"), None))
+        } else {
+            Ok(self)
+        }
+    }
+
+    // Product with the DFA of the exclude; paths whose text matches it are dropped.
+    fn exclude(&self, regex: &Regex) -> Result<Self, Error> {
+        let dfa = dense::Builder::new()
+            .configure(dense::Config::new().match_kind(MatchKind::All).start_kind(StartKind::Unanchored))
+            .build(regex.as_str())
+            .map_err(|x| Error::ErrorMessage(format!("Exclude cannot be analysed: {}", x), None))?;
+        let start_id = dfa.start_state(&start::Config::new().anchored(Anchored::No))
+            .map_err(|x| Error::ErrorMessage(format!("Exclude cannot be analysed: {}", x), None))?;
+
+        let mut result = Self::nothing();
+        let mut indexes = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        indexes.insert((self.start, start_id), result.start);
+        queue.push_back((self.start, start_id));
+
+        while let Some((state, id)) = queue.pop_front() {
+            let from = indexes[&(state, id)];
+
+            for edge in self.states[state].iter() {
+                let next_id = match edge.label {
+                    Some(byte) => dfa.next_state(id, byte),
+                    None => id,
+                };
+                if dfa.is_match_state(next_id) {
+                    continue;
+                }
+
+                let to = match indexes.get(&(edge.target, next_id)) {
+                    Some(to) => *to,
+                    None => {
+                        result.states.push(vec![]);
+                        let to = result.states.len() - 1;
+                        indexes.insert((edge.target, next_id), to);
+                        queue.push_back((edge.target, next_id));
+                        to
+                    }
+                };
+                result.states[from].push(Edge { label: edge.label, weight: edge.weight, target: to });
+            }
+
+            if state == self.accept && !dfa.is_match_state(dfa.next_eoi_state(id)) {
+                let accept = result.accept;
+                result.states[from].push(Edge { label: None, weight: 1.0, target: accept });
+            }
+
+            if result.states.len() > MAX_STATES {
+                return Err(Error::ErrorMessage(String::from("Grammar is too large to analyse"), None));
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn get_topological_order(&self) -> Vec<usize> {
+        let mut degrees = vec![0usize; self.states.len()];
+        for edge in self.states.iter().flatten() {
+            degrees[edge.target] += 1;
+        }
+
+        let mut order: Vec<usize> = (0..self.states.len()).filter(|x| degrees[*x] == 0).collect();
+        let mut index = 0;
+        while index < order.len() {
+            for edge in self.states[order[index]].iter() {
+                degrees[edge.target] -= 1;
+                if degrees[edge.target] == 0 {
+                    order.push(edge.target);
+                }
+            }
+            index += 1;
+        }
+
+        order
+    }
+
+    // Sum of the weights of every path spelling `word`.
+    pub(crate) fn weight(&self, word: &[u8]) -> f64 {
+        let order = self.get_topological_order();
+        let mut current = vec![0.0; self.states.len()];
+        current[self.start] = 1.0;
+
+        for position in 0..=word.len() {
+            let mut next = vec![0.0; self.states.len()];
+            for state in order.iter() {
+                let weight = current[*state];
+                if weight == 0.0 {
+                    continue;
+                }
+
+                for edge in self.states[*state].iter() {
+                    match edge.label {
+                        None => current[edge.target] += weight * edge.weight,
+                        Some(byte) if word.get(position) == Some(&byte) => next[edge.target] += weight * edge.weight,
+                        Some(_) => {},
+                    }
+                }
+            }
+
+            if position == word.len() {
+                return current[self.accept];
+            }
+            current = next;
+        }
+
+        0.0
+    }
+
+    // Sum of the weights of every path; the probability that a derivation is not excluded.
+    pub(crate) fn get_total_weight(&self) -> f64 {
+        self.get_backward_weights()[self.start]
+    }
+
+    // Sum of the weights of every path from each state to the accept state.
+    fn get_backward_weights(&self) -> Vec<f64> {
+        let mut weights = vec![0.0; self.states.len()];
+        weights[self.accept] = 1.0;
+
+        for state in self.get_topological_order().iter().rev() {
+            let weight: f64 = self.states[*state].iter().map(|x| x.weight * weights[x.target]).sum();
+            if *state != self.accept {
+                weights[*state] = weight;
+            }
+        }
+
+        weights
+    }
+
+    // Every number of characters a word with a positive weight can have.
+    pub(crate) fn char_lengths(&self) -> BTreeSet<usize> {
+        let mut lengths: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); self.states.len()];
+        lengths[self.accept].insert(0);
+
+        for state in self.get_topological_order().iter().rev() {
+            let mut result = BTreeSet::new();
+            for edge in self.states[*state].iter().filter(|x| x.weight > 0.0) {
+                // continuation bytes of UTF-8 do not start a character.
+                let length = edge.label.map_or(0, |x| usize::from(x & 0xC0 != 0x80));
+                result.extend(lengths[edge.target].iter().map(|x| x + length));
+            }
+            if *state != self.accept {
+                lengths[*state] = result;
+            }
+        }
+
+        std::mem::take(&mut lengths[self.start])
+    }
+
+    fn closure(&self, states: &mut Vec<usize>) {
+        let mut stack = states.clone();
+        let mut visited: HashSet<usize> = states.iter().copied().collect();
+
+        while let Some(state) = stack.pop() {
+            for edge in self.states[state].iter().filter(|x| x.label.is_none()) {
+                if visited.insert(edge.target) {
+                    stack.push(edge.target);
+                    states.push(edge.target);
+                }
+            }
+        }
+
+        states.sort_unstable();
+    }
+}
+
+struct Compiler<'a> {
+    variables: &'a HashMap<String, VariableData>,
+    compiled: HashMap<String, Rc<Nfa>>,
+    used_variables: Vec<String>,
+}
+
+// Generate statements are weighted like the patterns of an expression.
+pub(crate) fn compile(scopes: &[GenerateScope]) -> Result<Nfa, Error> {
+    let max: f64 = scopes.iter().map(|x| x.weight).filter(|x| *x > 0.0).sum();
+
+    let mut items = Vec::new();
+    for scope in scopes.iter().filter(|x| x.weight > 0.0) {
+        items.push((compile_scope(scope, true)?, scope.weight / max));
+    }
+
+    match items.len() {
+        0 => Err(Error::NotFoundPattern),
+        1 => Ok(items.remove(0).0),
+        _ => Nfa::union(&items),
+    }
+}
+
+pub(crate) fn compile_scope(scope: &GenerateScope, with_excludes: bool) -> Result<Nfa, Error> {
+    let mut compiler = Compiler { variables: &scope.variables, compiled: HashMap::new(), used_variables: Vec::new() };
+
+    if with_excludes {
+        compiler.compile_expression(&scope.generate.expression)
+    } else {
+        compiler.compile_patterns(&scope.generate.expression.patterns)
+    }
+}
+
+impl Compiler<'_> {
+    fn compile_expression(&mut self, expression: &Expression) -> Result<Nfa, Error> {
+        let nfa = self.compile_patterns(&expression.patterns)?;
+
+        match &expression.excludes {
+            Exclude::Regex(regex) if !regex.as_str().is_empty() => nfa.exclude(regex),
+            _ => Ok(nfa),
+        }
+    }
+
+    fn compile_patterns(&mut self, patterns: &[Pattern]) -> Result<Nfa, Error> {
+        let max: f64 = patterns.iter().map(|x| x.count).filter(|x| *x > 0.0).sum();
+
+        let mut items = Vec::new();
+        for pattern in patterns.iter().filter(|x| x.count > 0.0) {
+            items.push((self.compile_pattern(pattern)?, pattern.count / max));
+        }
+
+        Nfa::union(&items)
+    }
+
+    fn compile_pattern(&mut self, pattern: &Pattern) -> Result<Nfa, Error> {
+        let mut items = Vec::new();
+        for value in pattern.values.iter() {
+            items.push(self.compile_value(value)?);
+        }
+
+        if !pattern.values.iter().any(|x| matches!(x, Value::Backreference(_))) {
+            return Nfa::concat(&items);
+        }
+
+        // Each string a referred value can produce becomes its own alternative,
+        // with the value and its backreferences fixed to that string.
+        let mut referred = Vec::new();
+        for index in pattern.get_referred_indexes() {
+            let words: Vec<(String, f64)> = Enumerate::new(Dfa::new(&items[index])?, None)
+                .map(|word| { let weight = items[index].weight(word.as_bytes()); (word, weight) })
+                .collect();
+            referred.push((index, words));
+        }
+
+        let mut alternatives: Vec<(Vec<Nfa>, f64)> = vec![(items, 1.0)];
+        for (index, words) in referred.iter() {
+            alternatives = alternatives.into_iter().flat_map(|(items, weight)| {
+                words.iter().map(move |(word, word_weight)| {
+                    let mut items = items.clone();
+                    for (position, item) in items.iter_mut().enumerate() {
+                        if pattern.get_referred_index(position) == *index {
+                            *item = Nfa::literal(word);
+                        }
+                    }
+                    (items, weight * word_weight)
+                }).collect::<Vec<(Vec<Nfa>, f64)>>()
+            }).collect();
+        }
+
+        let mut result = Vec::new();
+        for (items, weight) in alternatives.iter() {
+            result.push((Nfa::concat(items)?, *weight));
+        }
+
+        Nfa::union(&result)
+    }
+
+    fn compile_value(&mut self, value: &Value) -> Result<Nfa, Error> {
+        match value {
+            Value::Literal(text) => Ok(Nfa::literal(text)),
+            Value::Variable(key) => {
+                if let Some(nfa) = self.compiled.get(key) {
+                    return Ok(nfa.as_ref().clone());
+                }
+                if self.used_variables.contains(key) {
+                    return Err(Error::ErrorMessage(format!("Grammar is infinite because of recursive variable: {}", key), None));
+                }
+
+                let data = self.variables.get(key).ok_or_else(|| Error::NotFoundVariable(key.to_owned()))?;
+                self.used_variables.push(key.to_owned());
+                let nfa = self.compile_expression(&data.expression);
+                self.used_variables.pop();
+
+                let nfa = Rc::new(nfa?);
+                self.compiled.insert(key.to_owned(), Rc::clone(&nfa));
+                Ok(nfa.as_ref().clone())
+            },
+            Value::InnerPattern(patterns) => self.compile_patterns(patterns),
+            // Replaced by the referred value in compile_pattern.
+            Value::Backreference(_) => Ok(Nfa::literal("")),
+            Value::Regex(pattern) => Err(Error::ErrorMessage(format!("Regex can only be used in excludes: /{}/", pattern), None)),
+            Value::Repeat(value, min, max) => {
+                let nfa = self.compile_value(value)?;
+
+                let mut items = Vec::new();
+                for count in *min..=*max {
+                    items.push((Nfa::concat(&vec![nfa.clone(); count])?, 1.0 / (max - min + 1) as f64));
+                }
+
+                Nfa::union(&items)
+            },
+        }
+    }
+}
+
+// Draws paths in proportion to their weight. Excluded paths are already removed
+// from the NFA, so every draw is a valid word with the distribution of retrying.
+#[derive(Debug, Clone)]
+pub(crate) struct Sampler {
+    items: Vec<SamplerItem>,
+}
+
+// One generate statement. `excludes` are the excludes which are not applied to the NFA.
+#[derive(Debug, Clone)]
+struct SamplerItem {
+    nfa: Nfa,
+    weights: Vec<f64>,
+    mass: f64,
+    excludes: Option<Exclude>,
+}
+
+impl Sampler {
+    pub(crate) fn new(items: Vec<(Nfa, f64, Option<Exclude>)>) -> Self {
+        let items = items.into_iter().map(|(nfa, weight, excludes)| {
+            let weights = nfa.get_backward_weights();
+            let mass = weight * weights[nfa.start];
+            SamplerItem { nfa, weights, mass, excludes }
+        }).collect();
+
+        Self { items }
+    }
+
+    // The word and the excludes which still have to be checked.
+    pub(crate) fn sample(&self, rng: &mut dyn RngCore) -> Result<(String, Option<&Exclude>), Error> {
+        let max: f64 = self.items.iter().map(|x| x.mass).sum();
+        if max <= 0.0 {
+            return Err(Error::OverRetryCount);
+        }
+
+        let item = match self.items.as_slice() {
+            [item] => item,
+            items => {
+                let value = rng.gen_range(0.0..max);
+                let mut sum = 0.0;
+                items.iter().filter(|x| x.mass > 0.0).find(|x| { sum += x.mass; value < sum })
+                    .or_else(|| items.iter().rev().find(|x| x.mass > 0.0))
+                    .ok_or(Error::NotFoundPattern)?
+            },
+        };
+
+        item.sample(rng).map(|word| (word, item.excludes.as_ref()))
+    }
+}
+
+impl SamplerItem {
+    fn sample(&self, rng: &mut dyn RngCore) -> Result<String, Error> {
+        let mut state = self.nfa.start;
+        let mut buffer: Vec<u8> = Vec::new();
+        while state != self.nfa.accept {
+            let value = rng.gen_range(0.0..self.weights[state]);
+
+            let mut sum = 0.0;
+            let mut next: Option<&Edge> = None;
+            for edge in self.nfa.states[state].iter() {
+                let weight = edge.weight * self.weights[edge.target];
+                if weight <= 0.0 {
+                    continue;
+                }
+
+                sum += weight;
+                next = Some(edge);
+                if value < sum {
+                    break;
+                }
+            }
+
+            let edge = next.ok_or(Error::NotFoundPattern)?;
+            buffer.extend(edge.label);
+            state = edge.target;
+        }
+
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DfaState {
+    accept: bool,
+    transitions: Vec<(u8, usize)>,
+}
+
+// Deterministic form of an `Nfa`; every word has exactly one path.
+#[derive(Debug, Clone)]
+pub(crate) struct Dfa {
+    states: Vec<DfaState>,
+}
+
+impl Dfa {
+    pub(crate) fn new(nfa: &Nfa) -> Result<Self, Error> {
+        let mut start = vec![nfa.start];
+        nfa.closure(&mut start);
+
+        let mut states: Vec<DfaState> = Vec::new();
+        let mut indexes: HashMap<Vec<usize>, usize> = HashMap::new();
+        let mut queue: VecDeque<Vec<usize>> = VecDeque::new();
+
+        indexes.insert(start.clone(), 0);
+        states.push(DfaState { accept: start.contains(&nfa.accept), transitions: vec![] });
+        queue.push_back(start);
+
+        while let Some(set) = queue.pop_front() {
+            let from = indexes[&set];
+
+            let mut targets: Vec<(u8, usize)> = set.iter()
+                .flat_map(|x| nfa.states[*x].iter())
+                .filter_map(|x| x.label.map(|label| (label, x.target)))
+                .collect();
+            targets.sort_unstable();
+            targets.dedup();
+
+            let mut index = 0;
+            while index < targets.len() {
+                let byte = targets[index].0;
+                let mut next: Vec<usize> = targets[index..].iter().take_while(|x| x.0 == byte).map(|x| x.1).collect();
+                index += next.len();
+                nfa.closure(&mut next);
+
+                let to = match indexes.get(&next) {
+                    Some(to) => *to,
+                    None => {
+                        states.push(DfaState { accept: next.contains(&nfa.accept), transitions: vec![] });
+                        let to = states.len() - 1;
+                        indexes.insert(next.clone(), to);
+                        queue.push_back(next);
+                        to
+                    },
+                };
+                states[from].transitions.push((byte, to));
+            }
+
+            if states.len() > MAX_STATES {
+                return Err(Error::ErrorMessage(String::from("Grammar is too large to analyse"), None));
+            }
+        }
+
+        let mut dfa = Self { states };
+        dfa.trim();
+        Ok(dfa)
+    }
+
+    // Number of distinct words; every word has exactly one path in the DFA.
+    pub(crate) fn count(&self) -> BigUint {
+        let mut counts: Vec<Option<BigUint>> = vec![None; self.states.len()];
+        let mut stack = vec![0];
+
+        while let Some(&state) = stack.last() {
+            if counts[state].is_some() {
+                stack.pop();
+                continue;
+            }
+
+            let pending: Vec<usize> = self.states[state].transitions.iter().map(|x| x.1).filter(|x| counts[*x].is_none()).collect();
+            if pending.is_empty() {
+                let mut count = BigUint::from(self.states[state].accept as u8);
+                for (_, target) in self.states[state].transitions.iter() {
+                    count += counts[*target].as_ref().unwrap();
+                }
+                counts[state] = Some(count);
+                stack.pop();
+            } else {
+                stack.extend(pending);
+            }
+        }
+
+        counts[0].take().unwrap_or_default()
+    }
+
+    // Drop transitions into states from which no word can be completed.
+    fn trim(&mut self) {
+        let mut productive: Vec<bool> = self.states.iter().map(|x| x.accept).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, state) in self.states.iter().enumerate() {
+                if !productive[index] && state.transitions.iter().any(|x| productive[x.1]) {
+                    productive[index] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        for state in self.states.iter_mut() {
+            state.transitions.retain(|x| productive[x.1]);
+        }
+    }
+}
+
+/// Iterator over every distinct word a `Data` can generate, in byte order.
+/// Declared segments are ordered after every other character.
+#[derive(Debug, Clone)]
+pub struct Enumerate {
+    dfa: Dfa,
+    stack: Vec<(usize, usize)>,
+    buffer: Vec<u8>,
+    started: bool,
+    segments: Option<SegmentsStruct>,
+}
+
+impl Enumerate {
+    pub(crate) fn new(dfa: Dfa, segments: Option<SegmentsStruct>) -> Self {
+        Self { dfa, stack: vec![(0, 0)], buffer: vec![], started: false, segments }
+    }
+
+    // Number of words the iterator yields from the start.
+    pub(crate) fn total(&self) -> BigUint {
+        self.dfa.count()
+    }
+
+    fn get_word(&self) -> String {
+        let word = String::from_utf8_lossy(&self.buffer);
+        match &self.segments {
+            Some(segments) => segments.decode(&word),
+            None => word.into_owned(),
+        }
+    }
+}
+
+impl Iterator for Enumerate {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            if self.dfa.states[0].accept {
+                return Some(String::default());
+            }
+        }
+
+        while let Some((state, position)) = self.stack.last_mut() {
+            if let Some((byte, target)) = self.dfa.states[*state].transitions.get(*position) {
+                *position += 1;
+                let target = *target;
+                self.buffer.push(*byte);
+                self.stack.push((target, 0));
+
+                if self.dfa.states[target].accept {
+                    return Some(self.get_word());
+                }
+            } else {
+                self.stack.pop();
+                self.buffer.pop();
+            }
+        }
+
+        None
+    }
+}
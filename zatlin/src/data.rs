@@ -1,31 +1,310 @@
-use std::{fs::File, io::Read};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use num_bigint::BigUint;
 
 use crate::error::Error;
-use crate::lexer::{lexer, lexer_by_vec};
-use crate::parser::{parse, Statement};
+use crate::lexer::{lexer, lexer_by_vec, normalize, Token};
+use crate::normalization::Normalization;
+use crate::parser::{parse, convert_statement_exclude, GenerateStruct, MapStruct, RewriteStruct, Statement, StressStruct, SyllableRole, LengthStruct, LengthUnit, SegmentsStruct};
+use crate::import::{read_text, resolve_imports};
+use crate::automaton::{compile, compile_scope, Dfa, Enumerate, Nfa, Sampler};
+use crate::derivation::{self, Derivation};
+use crate::syllable::{get_roles, syllabify, Syllable};
+use crate::length::syllable_range;
+use crate::segment::encode_segments;
 
 #[derive(Debug, Clone)]
 pub struct Data {
     statements: Vec<Statement>,
+    normalization: Normalization,
+    samplers: OnceLock<HashMap<Option<String>, Result<Sampler, Error>>>,
 } 
 
 impl Data {
+    fn new(statements: Vec<Statement>, normalization: Normalization) -> Self {
+        Self {
+            statements,
+            normalization,
+            samplers: OnceLock::new(),
+        }
+    }
+
+    /// Like `TryFrom<&str>`, with the literals and generated words normalized by `normalization`.
+    pub fn with_normalization(text: &str, normalization: Normalization) -> Result<Self, Error> {
+        Self::from_tokens(lexer(text), Path::new("."), Vec::new(), normalization)
+    }
+
+    pub(crate) fn get_normalization(&self) -> Normalization {
+        self.normalization
+    }
+
     pub(crate) fn get_statements_ref(&self) -> Result<&Vec<Statement>, Error> {
         Ok(self.statements.as_ref())
     }
 
+    // Compiled once and shared by every generation in `SamplingMode::Compiled`.
+    pub(crate) fn get_sampler(&self, name: Option<&str>) -> Result<&Sampler, Error> {
+        let samplers = self.samplers.get_or_init(|| {
+            std::iter::once(None).chain(self.generate_names().into_iter().map(Some))
+                .map(|x| (x.map(String::from), self.create_sampler(x)))
+                .collect()
+        });
+
+        match samplers.get(&name.map(String::from)) {
+            Some(sampler) => sampler.as_ref().map_err(|x| x.clone()),
+            None => Err(Error::NotFoundGenerate(name.unwrap_or_default().to_owned())),
+        }
+    }
+
+    // When rules rewrite (or normalization changes) the word before the excludes of generate,
+    // those excludes can only be checked after sampling.
+    fn create_sampler(&self, name: Option<&str>) -> Result<Sampler, Error> {
+        let scopes = crate::get_generate_scopes(self.get_statements_ref()?, name)?;
+        let late_excludes = self.get_rules().iter().any(|x| !x.after_exclude) || self.normalization != Normalization::None;
+        let max: f64 = scopes.iter().map(|x| x.weight).filter(|x| *x > 0.0).sum();
+
+        let mut items = Vec::new();
+        for scope in scopes.iter().filter(|x| x.weight > 0.0) {
+            let excludes = late_excludes.then(|| scope.generate.expression.excludes.clone());
+            items.push((compile_scope(scope, !late_excludes)?, scope.weight / max, excludes));
+        }
+
+        if items.is_empty() {
+            return Err(Error::NotFoundPattern);
+        }
+        Ok(Sampler::new(items))
+    }
+
+    // When several `@stress` statements are given, the last one is used.
+    pub(crate) fn get_stress(&self) -> Option<&StressStruct> {
+        self.statements.iter().rev().find_map(|x| match x {
+            Statement::Stress(stress) => Some(stress),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn get_lengths(&self) -> Vec<&LengthStruct> {
+        self.statements.iter().filter_map(|x| match x {
+            Statement::Length(length) => Some(length),
+            _ => None,
+        }).collect()
+    }
+
+    // Stress and the number of syllables are only known from the derivation.
+    pub(crate) fn needs_derivation(&self) -> bool {
+        self.get_stress().is_some() || self.get_lengths().iter().any(|x| x.unit == LengthUnit::Syllables)
+    }
+
+    // A constraint which no word of the grammar satisfies is reported here instead of by the retries.
+    // Segments are only known when no rewrite rule, stress mark nor normalization changes the words,
+    // and characters when no segment is declared, too.
+    fn check_lengths(self) -> Result<Self, Error> {
+        let scopes = crate::get_generate_scopes(&self.statements, None)?;
+        if scopes.is_empty() {
+            return Ok(self);
+        }
+
+        let plain = self.get_rules().is_empty() && self.get_stress().is_none() && self.normalization == Normalization::None;
+        let segmented = self.get_segments().is_some();
+        let mut char_lengths = None;
+        for length in self.get_lengths() {
+            let satisfiable = match length.unit {
+                LengthUnit::Syllables => {
+                    let (min, max) = syllable_range(&scopes, &self.get_roles());
+                    length.overlaps(min, max)
+                },
+                LengthUnit::Chars | LengthUnit::Graphemes if segmented => true,
+                // a word has no more graphemes than characters.
+                unit if plain => match char_lengths.get_or_insert_with(|| compile(&scopes).map(|x| x.char_lengths())) {
+                    Ok(lengths) if unit == LengthUnit::Graphemes => length.overlaps(0, lengths.last().copied()),
+                    Ok(lengths) => length.any(lengths.iter().copied()),
+                    // grammars which can not be analysed are only checked by generating.
+                    Err(_) => true,
+                },
+                _ => true,
+            };
+
+            if !satisfiable {
+                return Err(Error::Unsatisfiable(length.to_string()));
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub(crate) fn get_rules(&self) -> Vec<&RewriteStruct> {
+        self.statements.iter().filter_map(|x| match x {
+            Statement::Rewrite(rule) => Some(rule),
+            _ => None,
+        }).collect()
+    }
+
+    /// Names of the `map name { ... }` statements, in order of appearance.
+    pub fn map_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for statement in self.statements.iter() {
+            if let Statement::Map(MapStruct { name, .. }) = statement {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+
+        names
+    }
+
+    /// Spell `word` by the map `name`. When several maps share the name, the last one is used.
+    pub fn render(&self, word: &str, name: &str) -> Result<String, Error> {
+        self.statements.iter().rev()
+            .find_map(|x| match x {
+                Statement::Map(map) if map.name == name => Some(map.render(word)),
+                _ => None,
+            })
+            .ok_or_else(|| Error::NotFoundMap(name.to_owned()))
+    }
+
+    /// Names of the `%name = ...;` statements, in order of appearance.
+    pub fn generate_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for statement in self.statements.iter() {
+            if let Statement::Generate(GenerateStruct { name: Some(name), .. }) = statement {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+
+        names
+    }
+
+    /// Iterate over every distinct word the `%` statements can generate, with excludes applied.
+    /// Fails when the grammar is recursive (infinite) or too large to analyse,
+    /// or when it has rewrite rules, `@stress`, `@length` or normalization, which the analysis does not apply.
+    pub fn enumerate(&self) -> Result<Enumerate, Error> {
+        self.check_analysable()?;
+        self.get_dfa().map(|x| Enumerate::new(x, self.get_segments().cloned()))
+    }
+
+    /// Count the distinct words the `%` statements can generate, with excludes applied.
+    /// Words reachable through several derivations are counted once.
+    /// Fails like `enumerate`.
+    pub fn count_words(&self) -> Result<BigUint, Error> {
+        self.check_analysable()?;
+        self.get_dfa().map(|x| x.count())
+    }
+
+    /// Probability that the `%` statements generate `word`, summed over every derivation.
+    /// The result is conditioned on the derivation not being excluded, like the retries of generation.
+    /// Fails when the grammar has rewrite rules, `@stress`, `@length` or normalization.
+    pub fn probability(&self, word: &str) -> Result<f64, Error> {
+        self.check_analysable()?;
+        let nfa = self.get_nfa(None)?;
+        let total = nfa.get_total_weight();
+
+        if total > 0.0 {
+            Ok(nfa.weight(self.encode(word).as_bytes()) / total)
+        } else {
+            Err(Error::OverRetryCount)
+        }
+    }
+
+    /// Check whether the `%` statements can generate `word`.
+    /// This is false whenever `derive` fails, so use `derive` to tell a rejected word from an unsupported grammar.
+    pub fn accepts(&self, word: &str) -> bool {
+        matches!(self.derive(word), Ok(Some(_)))
+    }
+
+    /// Find a derivation of `word` by the `%` statements, with excludes applied.
+    /// Declared segments in `word` are read longest first.
+    /// Fails when the grammar has rewrite rules, `@stress`, `@length` or normalization.
+    pub fn derive(&self, word: &str) -> Result<Option<Derivation>, Error> {
+        self.check_analysable()?;
+        let scopes = crate::get_generate_scopes(self.get_statements_ref()?, None)?;
+        let word = self.encode(word);
+        Ok(scopes.iter()
+            .filter(|x| x.weight > 0.0)
+            .find_map(|x| derivation::derive(&word, &x.generate, &x.variables))
+            .map(|x| self.decode_derivation(&x)))
+    }
+
+    // The analyses read the grammar itself, so they would be wrong for words which are changed or dropped after generation.
+    // Normalization can merge words of distinct derivations and make them match excludes they did not match before.
+    fn check_analysable(&self) -> Result<(), Error> {
+        let changed = !self.get_rules().is_empty() || self.get_stress().is_some() || self.normalization != Normalization::None;
+        if changed || !self.get_lengths().is_empty() {
+            return Err(Error::ErrorMessage(String::from("Analysis is not supported with rewrite, @stress, @length or normalization"), None));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn get_segments(&self) -> Option<&SegmentsStruct> {
+        self.statements.iter().find_map(|x| match x {
+            Statement::Segments(segments) => Some(segments),
+            _ => None,
+        })
+    }
+
+    fn encode(&self, word: &str) -> String {
+        self.get_segments().map_or_else(|| word.to_owned(), |x| x.encode(word))
+    }
+
+    // The word as it is output.
+    pub(crate) fn decode(&self, word: &str) -> String {
+        let word = self.get_segments().map_or_else(|| word.to_owned(), |x| x.decode(word));
+        self.normalization.apply(&word)
+    }
+
+    pub(crate) fn decode_derivation(&self, derivation: &Derivation) -> Derivation {
+        self.get_segments().map_or_else(|| derivation.clone(), |x| x.decode_derivation(derivation))
+    }
+
+    /// Split a derivation into the syllables marked by `@syllable`.
+    pub fn syllabify(&self, derivation: &Derivation) -> Vec<Syllable> {
+        syllabify(derivation, &self.get_roles())
+    }
+
+    pub(crate) fn get_roles(&self) -> HashMap<&str, SyllableRole> {
+        get_roles(&self.statements)
+    }
+
+    fn get_nfa(&self, name: Option<&str>) -> Result<Nfa, Error> {
+        let scopes = crate::get_generate_scopes(self.get_statements_ref()?, name)?;
+        compile(&scopes)
+    }
+
+    fn get_dfa(&self) -> Result<Dfa, Error> {
+        Dfa::new(&self.get_nfa(None)?)
+    }
+
+    /// `@import` paths are resolved relative to the directory of `filename`.
     pub fn read_file<P>(filename: P) -> Result<Self, Error>
     where
         P: AsRef<std::path::Path>
     {
-        let text = {
-            let mut f = File::open(filename).map_err(|_| Error::ErrorMessage(String::from("file not found"), None))?;
-            let mut contents = String::new();
-            f.read_to_string(&mut contents).map_err(|x| Error::ErrorMessage(x.to_string(), None))?;
-            contents
-        };
+        Self::read_file_with_normalization(filename, Normalization::None)
+    }
 
-        Self::try_from(text)
+    /// Like `read_file`, with the literals and generated words normalized by `normalization`.
+    pub fn read_file_with_normalization<P>(filename: P, normalization: Normalization) -> Result<Self, Error>
+    where
+        P: AsRef<std::path::Path>
+    {
+        let path = filename.as_ref().canonicalize().map_err(|_| Error::ErrorMessage(String::from("file not found"), None))?;
+        let text = read_text(&path)?;
+
+        let base = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        Self::from_tokens(lexer(&text), &base, vec![path], normalization)
+    }
+
+    // `@import` paths in text which is not read from a file are resolved relative to the current directory.
+    fn from_tokens(tokens: Vec<Token>, base: &Path, mut files: Vec<PathBuf>, normalization: Normalization) -> Result<Self, Error> {
+        let statements = parse(&normalize(tokens, normalization)?)?;
+        let statements = encode_segments(resolve_imports(statements, base, &mut files, normalization)?)?;
+
+        convert_statement_exclude(statements).map(|x| Self::new(x, normalization))?.check_lengths()
     }
 }
 
@@ -34,9 +313,7 @@ impl TryFrom<Vec<&str>> for Data {
 
     fn try_from(value: Vec<&str>) -> Result<Self, Self::Error> {
         let tokens = lexer_by_vec(value);
-        parse(&tokens).map(|statements| Self {
-            statements,
-        })
+        Self::from_tokens(tokens, Path::new("."), Vec::new(), Normalization::None)
     }
 }
 
@@ -45,9 +322,7 @@ impl TryFrom<&str> for Data {
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         let tokens = lexer(value);
-        parse(&tokens).map(|statements| Self {
-            statements,
-        })
+        Self::from_tokens(tokens, Path::new("."), Vec::new(), Normalization::None)
     }
 }
 
@@ -56,9 +331,7 @@ impl TryFrom<String> for Data {
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
         let tokens = lexer(&value);
-        parse(&tokens).map(|statements| Self {
-            statements,
-        })
+        Self::from_tokens(tokens, Path::new("."), Vec::new(), Normalization::None)
     }
 }
 
@@ -67,9 +340,7 @@ impl TryFrom<&String> for Data {
 
     fn try_from(value: &String) -> Result<Self, Self::Error> {
         let tokens = lexer(value);
-        parse(&tokens).map(|statements| Self {
-            statements,
-        })
+        Self::from_tokens(tokens, Path::new("."), Vec::new(), Normalization::None)
     }
 }
 
@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{Exclude, Expression, Pattern, Value};
+use crate::VariableData;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DerivationKind {
+    Generate,
+    Variable(String),
+    InnerPattern,
+    /// A quantified value. `pattern` of the node is the number of repetitions.
+    Repeat,
+}
+
+/// How a word was built: which pattern of each expression was chosen and the text it produced.
+/// Children are the variables and inner patterns of the chosen pattern, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Derivation {
+    pub kind: DerivationKind,
+    pub pattern: usize,
+    pub text: String,
+    /// Byte offset of `text` in the text of the parent node.
+    pub offset: usize,
+    pub children: Vec<Derivation>,
+}
+
+impl Derivation {
+    pub(crate) fn new(kind: DerivationKind, pattern: usize, text: String, children: Vec<Derivation>) -> Self {
+        Self { kind, pattern, text, offset: 0, children }
+    }
+
+    pub(crate) fn at(self, offset: usize) -> Self {
+        Self { offset, ..self }
+    }
+}
+
+struct Matcher<'a> {
+    word: &'a str,
+    variables: &'a HashMap<String, VariableData>,
+    // Results of variables which are being matched, grown until they stop changing
+    // so that left recursive variables terminate.
+    growing: HashMap<(String, usize), Vec<(usize, Derivation)>>,
+    // Growing results which were read, as they make the results built on them incomplete.
+    growing_reads: Vec<(String, usize)>,
+    // Complete results of variables.
+    memo: HashMap<(String, usize), Vec<(usize, Derivation)>>,
+}
+
+pub(crate) fn derive(word: &str, generate: &VariableData, variables: &HashMap<String, VariableData>) -> Option<Derivation> {
+    let mut matcher = Matcher { word, variables, growing: HashMap::new(), growing_reads: Vec::new(), memo: HashMap::new() };
+
+    matcher.match_expression(&generate.expression, DerivationKind::Generate, 0)
+        .into_iter()
+        .find(|(end, _)| *end == word.len())
+        .map(|(_, derivation)| derivation)
+}
+
+impl<'a> Matcher<'a> {
+    // Every end position the expression can reach from `start`, with one derivation for each.
+    fn match_expression(&mut self, expression: &Expression, kind: DerivationKind, start: usize) -> Vec<(usize, Derivation)> {
+        let mut result: Vec<(usize, Derivation)> = Vec::new();
+
+        for (index, pattern) in expression.patterns.iter().enumerate().filter(|(_, x)| x.count > 0.0) {
+            for (end, children) in self.match_pattern(pattern, start) {
+                if result.iter().any(|x| x.0 == end) {
+                    continue;
+                }
+
+                let text = &self.word[start..end];
+                if let Exclude::Regex(regex) = &expression.excludes {
+                    if regex.is_match(text) {
+                        continue;
+                    }
+                }
+
+                result.push((end, Derivation::new(kind.clone(), index, text.to_owned(), children)));
+            }
+        }
+
+        result
+    }
+
+    fn match_pattern(&mut self, pattern: &Pattern, start: usize) -> Vec<(usize, Vec<Derivation>)> {
+        let word: &'a str = self.word;
+        let referred: Vec<usize> = pattern.values.iter().filter_map(get_backreference).collect();
+        let mut states: Vec<(usize, Vec<&'a str>, Vec<Derivation>)> = vec![(start, vec![], vec![])];
+
+        for value in pattern.values.iter() {
+            let mut next_states = Vec::new();
+            // States which end at the same position with the same referred texts only differ in their derivation,
+            // so only the first one is kept.
+            let mut seen: HashSet<(usize, Vec<Option<&'a str>>)> = HashSet::new();
+
+            for (position, matched, children) in states.into_iter() {
+                for (end, child) in self.match_value(value, position, &matched) {
+                    let mut matched = matched.clone();
+                    matched.push(&word[position..end]);
+                    if !seen.insert((end, referred.iter().map(|x| matched.get(*x).copied()).collect())) {
+                        continue;
+                    }
+                    let mut children = children.clone();
+                    children.extend(child.map(|x| x.at(position - start)));
+                    next_states.push((end, matched, children));
+                }
+            }
+
+            states = next_states;
+        }
+
+        states.into_iter().map(|(end, _, children)| (end, children)).collect()
+    }
+
+    fn match_value(&mut self, value: &Value, start: usize, matched: &[&str]) -> Vec<(usize, Option<Derivation>)> {
+        let rest = &self.word[start..];
+
+        match value {
+            Value::Literal(text) => {
+                if rest.starts_with(text.as_str()) { vec![(start + text.len(), None)] } else { vec![] }
+            },
+            Value::Backreference(index) => {
+                match matched.get(*index) {
+                    Some(text) if rest.starts_with(text) => vec![(start + text.len(), None)],
+                    _ => vec![],
+                }
+            },
+            Value::Variable(key) => {
+                self.match_variable(key, start).into_iter().map(|(end, x)| (end, Some(x))).collect()
+            },
+            Value::InnerPattern(patterns) => {
+                let expression = Expression { patterns: patterns.to_owned(), excludes: Exclude::Pattern(Vec::default()) };
+                self.match_expression(&expression, DerivationKind::InnerPattern, start).into_iter().map(|(end, x)| (end, Some(x))).collect()
+            },
+            Value::Regex(_) => vec![],
+            Value::Repeat(value, min, max) => {
+                let mut result: Vec<(usize, Option<Derivation>)> = Vec::new();
+                let mut states: Vec<(usize, Vec<Derivation>)> = vec![(start, vec![])];
+
+                for count in 0..=*max {
+                    if count >= *min {
+                        for (end, children) in states.iter() {
+                            if !result.iter().any(|x| x.0 == *end) {
+                                let text = self.word[start..*end].to_owned();
+                                result.push((*end, Some(Derivation::new(DerivationKind::Repeat, count, text, children.clone()))));
+                            }
+                        }
+                    }
+
+                    let mut next_states: Vec<(usize, Vec<Derivation>)> = Vec::new();
+                    for (position, children) in states.into_iter() {
+                        for (end, child) in self.match_value(value, position, matched) {
+                            if next_states.iter().any(|x| x.0 == end) {
+                                continue;
+                            }
+                            let mut children = children.clone();
+                            children.extend(child.map(|x| x.at(position - start)));
+                            next_states.push((end, children));
+                        }
+                    }
+                    states = next_states;
+                }
+
+                result
+            },
+        }
+    }
+
+    fn match_variable(&mut self, key: &str, start: usize) -> Vec<(usize, Derivation)> {
+        let data = match self.variables.get(key) {
+            Some(data) => data,
+            None => return vec![],
+        };
+
+        let memo_key = (key.to_owned(), start);
+        if let Some(result) = self.memo.get(&memo_key) {
+            return result.clone();
+        }
+        if let Some(result) = self.growing.get(&memo_key) {
+            self.growing_reads.push(memo_key);
+            return result.clone();
+        }
+
+        let reads = self.growing_reads.len();
+        self.growing.insert(memo_key.clone(), vec![]);
+        loop {
+            let result = self.match_expression(&data.expression, DerivationKind::Variable(key.to_owned()), start);
+            if result.len() <= self.growing[&memo_key].len() {
+                break;
+            }
+            self.growing.insert(memo_key.clone(), result);
+        }
+
+        // Reads of its own growing result are resolved by now, but not those of the variables which contain it.
+        let result = self.growing.remove(&memo_key).unwrap_or_default();
+        if self.growing_reads[reads..].iter().all(|x| *x == memo_key) {
+            self.growing_reads.truncate(reads);
+            self.memo.insert(memo_key, result.clone());
+        }
+
+        result
+    }
+}
+
+fn get_backreference(value: &Value) -> Option<usize> {
+    match value {
+        Value::Backreference(index) => Some(*index),
+        Value::Repeat(value, _, _) => get_backreference(value),
+        _ => None,
+    }
+}
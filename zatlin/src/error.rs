@@ -8,8 +8,14 @@ pub enum Error {
     UnknownToken(String, usize),
     NotFoundPattern,
     NotFoundVariable(String),
+    NotFoundGenerate(String),
+    NotFoundMap(String),
     OverRetryCount,
+    Exhausted(Vec<String>),
     ErrorMessage(String, Option<usize>),
+    InFile(String, Box<Error>),
+    InvalidRegex(String, u64, u64),
+    Unsatisfiable(String),
 }
 
 impl Display for Error {
@@ -20,13 +26,19 @@ impl Display for Error {
             Self::UnknownToken(token, index) => write!(f, "Unknown token : {}, index: {}", token, index),
             Self::NotFoundPattern => write!(f, "Not found patterns."),
             Self::NotFoundVariable(key) => write!(f, "Not found variable: {}", key),
+            Self::NotFoundGenerate(name) => write!(f, "Not found generate: {}", name),
+            Self::NotFoundMap(name) => write!(f, "Not found map: {}", name),
             Self::OverRetryCount => write!(f, "Retry count is over limit."),
+            Self::Exhausted(words) => write!(f, "Not enough distinct words: found {}", words.len()),
             Self::ErrorMessage(message, index) => {
                 match index {
                     Some(index) => write!(f, "{}: index: {}", message, index),
                     None => write!(f, "{}", message),
                 }
-            }
+            },
+            Self::InFile(file, error) => write!(f, "{} (in {})", error, file),
+            Self::InvalidRegex(pattern, row, column) => write!(f, "Invalid regex : /{}/, row: {}, column: {}", pattern, row, column),
+            Self::Unsatisfiable(constraint) => write!(f, "No word can satisfy {}", constraint),
         }
     }
 }
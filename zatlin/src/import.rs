@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::lexer::{lexer, normalize};
+use crate::normalization::Normalization;
+use crate::parser::{parse, DefineStruct, Exclude, Expression, ImportStruct, Pattern, Statement, Value};
+
+// Replace `@import` statements with the variables defined in the imported files.
+// `files` holds the files being imported, to detect cycles.
+pub(crate) fn resolve_imports(statements: Vec<Statement>, base: &Path, files: &mut Vec<PathBuf>, normalization: Normalization) -> Result<Vec<Statement>, Error> {
+    let mut result: Vec<Statement> = Vec::new();
+
+    for statement in statements.into_iter() {
+        match statement {
+            Statement::Import(import) => result.extend(import_file(&import, base, files, normalization)?),
+            statement => result.push(statement),
+        }
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn read_text(path: &Path) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|x| match x.kind() {
+        std::io::ErrorKind::NotFound => Error::ErrorMessage(String::from("file not found"), None),
+        _ => Error::ErrorMessage(x.to_string(), None),
+    })
+}
+
+// Only the variables of the imported file are used. Its generate statements are ignored.
+fn import_file(import: &ImportStruct, base: &Path, files: &mut Vec<PathBuf>, normalization: Normalization) -> Result<Vec<Statement>, Error> {
+    let path = base.join(&import.path);
+    let path = path.canonicalize().map_err(|_| Error::ErrorMessage(format!("file not found: {}", path.display()), None))?;
+
+    if files.contains(&path) {
+        let cycle: Vec<String> = files.iter().chain(std::iter::once(&path)).map(|x| x.display().to_string()).collect();
+        return Err(Error::ErrorMessage(format!("Import cycle: {}", cycle.join(" -> ")), None));
+    }
+
+    files.push(path.clone());
+    let statements = read_text(&path)
+        .and_then(|text| parse(&normalize(lexer(&text), normalization)?))
+        .and_then(|x| resolve_imports(x, path.parent().unwrap_or(base), files, normalization))
+        .map_err(|x| Error::InFile(path.display().to_string(), Box::new(x)));
+    files.pop();
+
+    let defines: Vec<DefineStruct> = statements?.into_iter().filter_map(|x| match x {
+        Statement::Define(define) => Some(define),
+        _ => None,
+    }).collect();
+
+    let namespace = match &import.namespace {
+        Some(namespace) => namespace,
+        None => return Ok(defines.into_iter().map(Statement::Define).collect()),
+    };
+
+    let names: HashSet<String> = defines.iter().map(|x| x.name.clone()).collect();
+    Ok(defines.into_iter().map(|x| Statement::Define(DefineStruct {
+        name: format!("{}.{}", namespace, x.name),
+        expr: Arc::new(rename_expression(&x.expr, namespace, &names)),
+    })).collect())
+}
+
+fn rename_expression(expression: &Expression, namespace: &str, names: &HashSet<String>) -> Expression {
+    let excludes = match &expression.excludes {
+        Exclude::Pattern(patterns) => Exclude::Pattern(rename_patterns(patterns, namespace, names)),
+        Exclude::Regex(regex) => Exclude::Regex(regex.clone()),
+    };
+
+    Expression { patterns: rename_patterns(&expression.patterns, namespace, names), excludes }
+}
+
+fn rename_patterns(patterns: &[Pattern], namespace: &str, names: &HashSet<String>) -> Vec<Pattern> {
+    patterns.iter().map(|pattern| {
+        let values = pattern.values.iter().map(|value| rename_value(value, namespace, names)).collect();
+
+        Pattern { values, ..pattern.clone() }
+    }).collect()
+}
+
+fn rename_value(value: &Value, namespace: &str, names: &HashSet<String>) -> Value {
+    match value {
+        Value::Variable(name) if names.contains(name) => Value::Variable(format!("{}.{}", namespace, name)),
+        Value::InnerPattern(patterns) => Value::InnerPattern(rename_patterns(patterns, namespace, names)),
+        Value::Repeat(value, min, max) => Value::Repeat(Box::new(rename_value(value, namespace, names)), *min, *max),
+        value => value.clone(),
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::parser::{Expression, LengthStruct, LengthUnit, Pattern, SyllableRole, Value};
+use crate::{GenerateScope, VariableData};
+
+impl LengthStruct {
+    fn contains(&self, length: usize) -> bool {
+        self.min <= length && self.max.is_none_or(|x| length <= x)
+    }
+
+    // `segments` is the number of segments of the word, and `syllables`
+    // the number of syllables of the derivation when it is known.
+    pub(crate) fn accepts(&self, word: &str, segments: usize, syllables: Option<usize>) -> bool {
+        match self.unit {
+            LengthUnit::Chars => self.contains(word.chars().count()),
+            LengthUnit::Graphemes => self.contains(word.graphemes(true).count()),
+            LengthUnit::Segments => self.contains(segments),
+            LengthUnit::Syllables => syllables.is_none_or(|x| self.contains(x)),
+        }
+    }
+
+    // Whether the range of lengths from `min` to `max` has any length of this constraint.
+    pub(crate) fn overlaps(&self, min: usize, max: Option<usize>) -> bool {
+        max.is_none_or(|x| self.min <= x) && self.max.is_none_or(|x| min <= x)
+    }
+
+    pub(crate) fn any(&self, mut lengths: impl Iterator<Item = usize>) -> bool {
+        lengths.any(|x| self.contains(x))
+    }
+}
+
+impl Display for LengthStruct {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = match self.unit {
+            LengthUnit::Chars => "chars",
+            LengthUnit::Graphemes => "graphemes",
+            LengthUnit::Segments => "segments",
+            LengthUnit::Syllables => "syllables",
+        };
+        match self.max {
+            Some(max) => write!(f, "@length {} {{{},{}}}", unit, self.min, max),
+            None => write!(f, "@length {} {{{},}}", unit, self.min),
+        }
+    }
+}
+
+// The fewest and the most syllables the scopes can generate, ignoring excludes.
+// The most is None when it is unbounded.
+pub(crate) fn syllable_range(scopes: &[GenerateScope], roles: &HashMap<&str, SyllableRole>) -> (usize, Option<usize>) {
+    let ranges: Vec<(usize, Option<usize>)> = scopes.iter().filter(|x| x.weight > 0.0).map(|scope| {
+        let mut counter = SyllableCounter { variables: &scope.variables, roles, visiting: HashSet::new() };
+        counter.count_expression(&scope.generate.expression)
+    }).collect();
+
+    join(&ranges)
+}
+
+fn join(ranges: &[(usize, Option<usize>)]) -> (usize, Option<usize>) {
+    let min = ranges.iter().map(|x| x.0).min().unwrap_or(0);
+    let max = ranges.iter().try_fold(0, |max, x| x.1.map(|x| max.max(x)));
+    (min, max)
+}
+
+struct SyllableCounter<'a> {
+    variables: &'a HashMap<String, VariableData>,
+    roles: &'a HashMap<&'a str, SyllableRole>,
+    visiting: HashSet<&'a str>,
+}
+
+impl<'a> SyllableCounter<'a> {
+    fn count_expression(&mut self, expression: &'a Expression) -> (usize, Option<usize>) {
+        let ranges: Vec<(usize, Option<usize>)> = expression.patterns.iter()
+            .filter(|x| x.count > 0.0)
+            .map(|x| self.count_pattern(x))
+            .collect();
+
+        join(&ranges)
+    }
+
+    // Text copied by a backreference has no derivation, so it has no syllables either.
+    fn count_pattern(&mut self, pattern: &'a Pattern) -> (usize, Option<usize>) {
+        pattern.values.iter().fold((0, Some(0)), |(min, max), value| {
+            let (value_min, value_max) = self.count_value(value);
+            (min + value_min, max.zip(value_max).map(|(x, y)| x + y))
+        })
+    }
+
+    fn count_value(&mut self, value: &'a Value) -> (usize, Option<usize>) {
+        match value {
+            Value::Variable(key) if self.roles.get(key.as_str()) == Some(&SyllableRole::Syllable) => (1, Some(1)),
+            Value::Variable(key) => {
+                let Some(data) = self.variables.get(key) else { return (0, Some(0)) };
+                // a recursive variable can be as long as it likes.
+                if !self.visiting.insert(key) {
+                    return (0, None);
+                }
+                let range = self.count_expression(&data.expression);
+                self.visiting.remove(key.as_str());
+                range
+            },
+            Value::InnerPattern(patterns) => {
+                let ranges: Vec<(usize, Option<usize>)> = patterns.iter().filter(|x| x.count > 0.0).map(|x| self.count_pattern(x)).collect();
+                join(&ranges)
+            },
+            Value::Repeat(value, min, max) => {
+                let (value_min, value_max) = self.count_value(value);
+                (value_min * min, value_max.map(|x| x * max))
+            },
+            Value::Literal(_) | Value::Backreference(_) | Value::Regex(_) => (0, Some(0)),
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use std::fmt::Display;
 
+use crate::error::Error;
+use crate::normalization::Normalization;
+
 
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) struct Token {
@@ -24,6 +27,13 @@ pub(crate) enum TokenType {
     LeftCirc,
     RightCirc,
     Ampersand(u32),
+    Directive(String),
+    Question,
+    LeftBrace,
+    RightBrace,
+    Comma,
+    Regex(String),
+    Arrow,
 }
 
 impl Token {
@@ -62,7 +72,14 @@ impl Display for TokenType {
             Self::NewLine => write!(f, "(NewLine)"),
             Self::LeftCirc => write!(f, "("),
             Self::RightCirc => write!(f, ")"),
-            Self::Ampersand(index) => write!(f, "{}", index + 1),
+            Self::Ampersand(index) => write!(f, "&{}", index + 1),
+            Self::Directive(name) => write!(f, "@{}", name),
+            Self::Question => write!(f, "?"),
+            Self::LeftBrace => write!(f, "{{"),
+            Self::RightBrace => write!(f, "}}"),
+            Self::Comma => write!(f, ","),
+            Self::Regex(pattern) => write!(f, "/{}/", pattern),
+            Self::Arrow => write!(f, "->"),
         }
     }
 }
@@ -72,6 +89,7 @@ enum TokenizeMode {
     Normal,
     String,
     Comment,
+    Regex,
 }
 
 pub(crate) fn lexer(text: &str) -> Vec<Token> {
@@ -83,9 +101,39 @@ pub(crate) fn lexer(text: &str) -> Vec<Token> {
     let mut row: u64 = 1;
     let mut column: u64 = 0;
     let mut length: u64 = 0;
+    let mut escaped = false;
 
     for c in text {
         match mode {
+            TokenizeMode::Regex => {
+                if c == '/' && !escaped {
+                    mode = TokenizeMode::Normal;
+                    buffer.push(c);
+                    length += 1;
+
+                    let token = String::from_iter(buffer.iter());
+                    tokens.push(get_value(row, column, &token));
+                    buffer.clear();
+
+                    column += length;
+                    length = 0;
+                } else if c == '\r' || c == '\n' {
+                    mode = TokenizeMode::Normal;
+
+                    let token = String::from_iter(buffer.iter());
+                    tokens.push(Token::new(row, column, TokenType::Unknown(token)));
+                    buffer.clear();
+
+                    tokens.push(Token::newline(row, column));
+                    row += 1;
+                    column = 0;
+                    length = 0;
+                } else {
+                    buffer.push(c);
+                    length += 1;
+                }
+                escaped = c == '\\' && !escaped;
+            },
             TokenizeMode::String => {
                 if c == '"' {
                     mode = TokenizeMode::Normal;
@@ -141,7 +189,7 @@ pub(crate) fn lexer(text: &str) -> Vec<Token> {
                     length = 0;
                 } else {
                     match c {
-                        '-' | '|' | ';' | '%' | '^' | '=' | '(' | ')' => {
+                        '-' | '|' | ';' | '%' | '^' | '=' | '(' | ')' | '?' | '{' | '}' | ',' => {
                             if !buffer.is_empty() {
                                 let token = String::from_iter(buffer.iter());
                                 tokens.push(get_value(row, column, &token));
@@ -182,6 +230,40 @@ pub(crate) fn lexer(text: &str) -> Vec<Token> {
                             length += 1;
                         
                         },
+                        '>' => {
+                            if !buffer.is_empty() {
+                                let token = String::from_iter(buffer.iter());
+                                tokens.push(get_value(row, column, &token));
+                                buffer.clear();
+
+                                column += length;
+                                length = 0;
+                            }
+
+                            // `->` is lexed as `-` first.
+                            match tokens.last_mut() {
+                                Some(token) if token.tokentype == TokenType::Minus && token.row == row && token.column + 1 == column => {
+                                    token.tokentype = TokenType::Arrow;
+                                },
+                                _ => tokens.push(get_token(row, column, c)),
+                            }
+                            column += 1;
+                        },
+                        '/' => {
+                            if !buffer.is_empty() {
+                                let token = String::from_iter(buffer.iter());
+                                tokens.push(get_value(row, column, &token));
+                                buffer.clear();
+
+                                column += length;
+                                length = 0;
+                            }
+
+                            mode = TokenizeMode::Regex;
+                            escaped = false;
+                            buffer.push(c);
+                            length += 1;
+                        },
                         '&' => {
                             if !buffer.is_empty() {
                                 let token = String::from_iter(buffer.iter());
@@ -227,6 +309,11 @@ pub(crate) fn lexer_by_vec(values: Vec<&str>) -> Vec<Token> {
             ";" => tokens.push(Token::new(1, 0, TokenType::Semicolon)),
             "(" => tokens.push(Token::new(1, 0, TokenType::LeftCirc)),
             ")" => tokens.push(Token::new(1, 0, TokenType::RightCirc)),
+            "?" => tokens.push(Token::new(1, 0, TokenType::Question)),
+            "{" => tokens.push(Token::new(1, 0, TokenType::LeftBrace)),
+            "}" => tokens.push(Token::new(1, 0, TokenType::RightBrace)),
+            "," => tokens.push(Token::new(1, 0, TokenType::Comma)),
+            "->" => tokens.push(Token::new(1, 0, TokenType::Arrow)),
             _ => {
                 if value.starts_with("\"") && value.ends_with("\"") {
                     let len = value.len();
@@ -240,6 +327,10 @@ pub(crate) fn lexer_by_vec(values: Vec<&str>) -> Vec<Token> {
                     tokens.push(Token::new(1, 0, token));
                 } else if let Ok(count) = value.parse() {
                     tokens.push(Token::new(1, 0, TokenType::Count(count)));
+                } else if let Some(index) = get_ampersand(value) {
+                    tokens.push(Token::new(1, 0, TokenType::Ampersand(index)));
+                } else if let Some(name) = get_directive(value) {
+                    tokens.push(Token::new(1, 0, TokenType::Directive(name)));
                 } else {
                     tokens.push(Token::new(1, 0, TokenType::Variable(String::from(value))));
                 }
@@ -250,9 +341,30 @@ pub(crate) fn lexer_by_vec(values: Vec<&str>) -> Vec<Token> {
     tokens
 }
 
+// Literals and the literal text of regexes are normalized, while names of variables are kept as they are written.
+pub(crate) fn normalize(mut tokens: Vec<Token>, normalization: Normalization) -> Result<Vec<Token>, Error> {
+    if normalization == Normalization::None {
+        return Ok(tokens);
+    }
+
+    for token in tokens.iter_mut() {
+        match &mut token.tokentype {
+            TokenType::Value(value) => *value = normalization.apply(value),
+            TokenType::Regex(pattern) => {
+                *pattern = normalization.apply_regex(pattern).ok_or_else(|| Error::InvalidRegex(pattern.clone(), token.row, token.column))?;
+            },
+            _ => {},
+        }
+    }
+
+    Ok(tokens)
+}
+
 fn get_value(row: u64, column: u64, value: &str) -> Token {
     let tokentype = if let Ok(num) = value.parse() {
         TokenType::Count(num)
+    } else if let Some(pattern) = get_regex(value) {
+        TokenType::Regex(pattern)
     } else if value.starts_with('"') {
         if value.ends_with('"') {
             let len = value.len();
@@ -266,8 +378,14 @@ fn get_value(row: u64, column: u64, value: &str) -> Token {
         } else {
             TokenType::Unknown(value.to_string())
         }
-    } else if let Some(index) = value.strip_prefix("&").and_then(|x| x.parse::<u32>().ok()) {
-        TokenType::Ampersand(index - 1)
+    } else if let Some(index) = get_ampersand(value) {
+        TokenType::Ampersand(index)
+    } else if value.starts_with('&') || value.starts_with('/') {
+        TokenType::Unknown(value.to_string())
+    } else if let Some(name) = get_directive(value) {
+        TokenType::Directive(name)
+    } else if value.starts_with('@') {
+        TokenType::Unknown(value.to_string())
     } else {
         TokenType::Variable(String::from(value))
     };
@@ -275,6 +393,22 @@ fn get_value(row: u64, column: u64, value: &str) -> Token {
     Token::new(row, column, tokentype)
 }
 
+fn get_ampersand(value: &str) -> Option<u32> {
+    value.strip_prefix('&').and_then(|x| x.parse::<u32>().ok()).and_then(|x| x.checked_sub(1))
+}
+
+// `\/` is only an escape for the delimiter, so it is unescaped before the regex is built.
+fn get_regex(value: &str) -> Option<String> {
+    value.strip_prefix('/')
+        .and_then(|x| x.strip_suffix('/'))
+        .filter(|x| !x.is_empty())
+        .map(|x| x.replace("\\/", "/"))
+}
+
+fn get_directive(value: &str) -> Option<String> {
+    value.strip_prefix('@').filter(|x| !x.is_empty()).map(String::from)
+}
+
 fn get_token(row: u64, column: u64, value: char) -> Token {
     let tokentype = match value {
         '-' => TokenType::Minus,
@@ -285,6 +419,10 @@ fn get_token(row: u64, column: u64, value: char) -> Token {
         '=' => TokenType::Equal,
         '(' => TokenType::LeftCirc,
         ')' => TokenType::RightCirc,
+        '?' => TokenType::Question,
+        '{' => TokenType::LeftBrace,
+        '}' => TokenType::RightBrace,
+        ',' => TokenType::Comma,
         _ => TokenType::Unknown(String::from(value)),
     };
 
@@ -293,6 +431,7 @@ fn get_token(row: u64, column: u64, value: char) -> Token {
 
 
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::match_like_matches_macro)]
 mod lexer_test {
     use super::{TokenType, Token};
 
@@ -436,4 +575,111 @@ mod lexer_test {
         println!("{:?}", unknown_tokens);
         assert!(unknown_tokens.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn backreference() {
+        let result = execute(r#"% C V &1 &2 - C V &1;"#);
+
+        println!("{:?}", result);
+
+        let references: Vec<&TokenType> = result.iter().filter_map(|x| {
+            match x.tokentype {
+                TokenType::Ampersand(_) => Some(&x.tokentype),
+                _ => None
+            }
+        }).collect();
+
+        assert_eq!(references, vec![&TokenType::Ampersand(0), &TokenType::Ampersand(1), &TokenType::Ampersand(0)]);
+    }
+
+    #[test]
+    fn invalid_backreference() {
+        let result = execute(r#"% C V &0;"#);
+
+        println!("{:?}", result);
+        assert!(result.iter().any(|x| x.tokentype == TokenType::Unknown(String::from("&0"))));
+    }
+
+    #[test]
+    fn directive() {
+        let result = execute(r#"@import "common.zatlin" as Common; % Common.C Common.V;"#);
+
+        println!("{:?}", result);
+        let types: Vec<&TokenType> = result.iter().map(|x| &x.tokentype).collect();
+        assert_eq!(types, vec![
+            &TokenType::Directive(String::from("import")),
+            &TokenType::Value(String::from("common.zatlin")),
+            &TokenType::Variable(String::from("as")),
+            &TokenType::Variable(String::from("Common")),
+            &TokenType::Semicolon,
+            &TokenType::Percent,
+            &TokenType::Variable(String::from("Common.C")),
+            &TokenType::Variable(String::from("Common.V")),
+            &TokenType::Semicolon,
+        ]);
+    }
+
+    #[test]
+    fn quantifier() {
+        let result = execute(r#"% C? (C V){2,4} V{1};"#);
+
+        println!("{:?}", result);
+        let types: Vec<String> = result.iter().map(|x| x.tokentype.to_string()).collect();
+        assert_eq!(types, vec!["%", "C", "?", "(", "C", "V", ")", "{", "2", ",", "4", "}", "V", "{", "1", "}", ";"]);
+    }
+
+    #[test]
+    fn regex() {
+        let result = execute("% C V - /[aeiou]{3}/ | /a\\/b/;\n% C - /[a-z\n");
+
+        println!("{:?}", result);
+        let regexes: Vec<&TokenType> = result.iter().filter(|x| matches!(x.tokentype, TokenType::Regex(_))).map(|x| &x.tokentype).collect();
+        assert_eq!(regexes, vec![&TokenType::Regex(String::from("[aeiou]{3}")), &TokenType::Regex(String::from("a/b"))]);
+
+        let regex = result.iter().find(|x| matches!(x.tokentype, TokenType::Regex(_))).unwrap();
+        assert_eq!((regex.row, regex.column), (1, 8));
+
+        assert!(result.iter().any(|x| x.tokentype == TokenType::Unknown(String::from("/[a-z"))));
+    }
+
+    #[test]
+    fn arrow() {
+        let result = execute(r#"rewrite "np" -> "mp" when _ /[aiu]/; % C - > V;"#);
+
+        println!("{:?}", result);
+        let types: Vec<String> = result.iter().map(|x| x.tokentype.to_string()).collect();
+        assert_eq!(types, vec!["rewrite", "\"np\"", "->", "\"mp\"", "when", "_", "/[aiu]/", ";", "%", "C", "-", ">", "V", ";"]);
+        assert!(matches!(result[11].tokentype, TokenType::Unknown(_)));
+    }
+
+    #[test]
+    fn normalize() {
+        let result = execute("V\u{301} = \"a\u{301}\" | \"\u{e9}\"; % V\u{301} - /\u{e1}/;");
+        let result = super::normalize(result, crate::normalization::Normalization::Nfc).unwrap();
+
+        println!("{:?}", result);
+        let types: Vec<&TokenType> = result.iter().map(|x| &x.tokentype).collect();
+        assert_eq!(types[0], &TokenType::Variable(String::from("V\u{301}")));
+        assert_eq!(types[2], &TokenType::Value(String::from("\u{e1}")));
+        assert_eq!(types[4], &TokenType::Value(String::from("\u{e9}")));
+        assert_eq!(types[9], &TokenType::Regex(String::from("\u{e1}")));
+
+        let result = super::normalize(result, crate::normalization::Normalization::Nfd).unwrap();
+        assert_eq!(result[4].tokentype, TokenType::Value(String::from("e\u{301}")));
+    }
+
+    #[test]
+    fn normalize_regex() {
+        use crate::normalization::Normalization;
+
+        // members of a class are single characters, so they are kept as written.
+        let result = super::normalize(execute("% \"a\" - /[a\u{301}]a\u{301}/;"), Normalization::Nfc).unwrap();
+        assert_eq!(result[3].tokentype, TokenType::Regex(String::from("[a\u{301}]\u{e1}")));
+
+        let result = super::normalize(execute("% \"a\" - /[ae]\u{e1}+|\\u{e1}/;"), Normalization::Nfd).unwrap();
+        assert_eq!(result[3].tokentype, TokenType::Regex(String::from("[ae](?:a\u{301})+|\\u{e1}")));
+
+        // a precomposed member can not match decomposed text.
+        assert!(super::normalize(execute("% \"a\" - /[\u{e1}\u{e9}]/;"), Normalization::Nfd).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+use std::{fs::File, io::Read};
+
+use crate::error::Error;
+use crate::normalization::Normalization;
+
+/// Existing words which generated words must not collide with.
+/// Words are compared in NFC, so precomposed and decomposed spellings of a word collide.
+#[derive(Debug, Clone, Default)]
+pub struct Lexicon {
+    words: HashSet<String>,
+    distance: usize,
+}
+
+impl Lexicon {
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>
+    {
+        Self {
+            words: words.into_iter().map(|x| Normalization::Nfc.apply(&x.into())).collect(),
+            distance: 0,
+        }
+    }
+
+    /// Read a file which has one word per line. Blank lines are ignored.
+    pub fn read_file<P>(filename: P) -> Result<Self, Error>
+    where
+        P: AsRef<std::path::Path>
+    {
+        let text = {
+            let mut f = File::open(filename).map_err(|_| Error::ErrorMessage(String::from("file not found"), None))?;
+            let mut contents = String::new();
+            f.read_to_string(&mut contents).map_err(|x| Error::ErrorMessage(x.to_string(), None))?;
+            contents
+        };
+
+        Ok(Self::new(text.lines().map(|x| x.trim()).filter(|x| !x.is_empty())))
+    }
+
+    /// Also reject words within `distance` edits (Levenshtein distance) of an existing word.
+    pub fn set_distance(&mut self, distance: usize) {
+        self.distance = distance;
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(&Normalization::Nfc.apply(word))
+    }
+
+    pub(crate) fn is_rejected(&self, word: &str) -> bool {
+        if self.distance == 0 {
+            return self.contains(word);
+        }
+
+        let word: Vec<char> = Normalization::Nfc.apply(word).chars().collect();
+        self.words.iter().any(|x| is_within_distance(&word, x, self.distance))
+    }
+}
+
+fn is_within_distance(word: &[char], other: &str, distance: usize) -> bool {
+    let other: Vec<char> = other.chars().collect();
+    if word.len().abs_diff(other.len()) > distance {
+        return false;
+    }
+
+    let mut previous: Vec<usize> = (0..=other.len()).collect();
+    for (i, a) in word.iter().enumerate() {
+        let mut current = vec![i + 1; other.len() + 1];
+        for (j, b) in other.iter().enumerate() {
+            let cost = if a == b { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        if current.iter().min().is_some_and(|x| *x > distance) {
+            return false;
+        }
+        previous = current;
+    }
+
+    previous[other.len()] <= distance
+}
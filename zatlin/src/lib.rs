@@ -1,37 +1,172 @@
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
 use rand::prelude::*;
 
 mod lexer;
 mod parser;
 mod error;
 mod data;
+mod automaton;
+mod derivation;
+mod lexicon;
+mod import;
+mod rewrite;
+mod orthography;
+mod syllable;
+mod stress;
+mod length;
+mod segment;
+mod normalization;
 use crate::parser::*;
-pub use crate::{error::Error, data::Data};
+pub use crate::{error::Error, data::Data, automaton::Enumerate, derivation::{Derivation, DerivationKind}, lexicon::Lexicon, syllable::{Syllable, Word}, normalization::Normalization};
+
+pub use num_bigint::BigUint;
 
 #[cfg(feature="use_macro")]
 pub use zatlin_macro::zatlin;
 
 pub struct Zatlin {
+    rng: Mutex<Box<dyn RngCore + Send>>,
+    mode: SamplingMode,
+    lexicon: Option<Lexicon>,
+    unique_miss_limit: u32,
+}
+
+/// How `Zatlin` deals with the excludes of the `%` statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplingMode {
+    /// Generate a word and retry it while it is excluded, up to the retry limit.
+    #[default]
+    Rejection,
+    /// Remove excluded words from the grammar beforehand, so that every draw is valid.
+    /// The distribution is the same as `Rejection` without the retry limit.
+    Compiled,
 }
 
 const DEFAULT_RETRY_COUNT: u32 = 100;
+const DEFAULT_UNIQUE_MISS_COUNT: u32 = 1000;
 
 impl Default for Zatlin {
     fn default() -> Self {
-        Self {
-        }
+        Self::with_rng(StdRng::from_entropy())
     }
 }
 
 impl Zatlin {
+    /// Create a generator which draws every random choice from `rng`.
+    /// Generating from the same `Data` with an equally seeded `rng` gives the same words.
+    pub fn with_rng<R>(rng: R) -> Self
+    where
+        R: RngCore + Send + 'static
+    {
+        Self {
+            rng: Mutex::new(Box::new(rng)),
+            mode: SamplingMode::default(),
+            lexicon: None,
+            unique_miss_limit: DEFAULT_UNIQUE_MISS_COUNT,
+        }
+    }
+
+    /// Create a generator which uses `StdRng` seeded by `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    // A panic while drawing cannot leave the rng in a state which matters, so poisoning is ignored.
+    fn rng(&self) -> MutexGuard<'_, Box<dyn RngCore + Send>> {
+        self.rng.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    /// `SamplingMode::Compiled` falls back to `SamplingMode::Rejection` for grammars which can not be compiled,
+    /// such as those with recursive variables.
+    pub fn set_sampling_mode(&mut self, mode: SamplingMode) {
+        self.mode = mode;
+    }
+
+    /// Reject generated words which collide with `lexicon`.
+    pub fn set_lexicon(&mut self, lexicon: Lexicon) {
+        self.lexicon = Some(lexicon);
+    }
+
+    /// Number of draws in a row without a new word after which `generate_unique` gives up.
+    /// Raise it for large grammars whose distribution is very skewed.
+    pub fn set_unique_miss_limit(&mut self, limit: u32) {
+        self.unique_miss_limit = limit;
+    }
+
     pub fn generate(&self, text: &str) -> Result<String, Error> {
         let data = Data::try_from(text)?;
-        data.get_statements_ref().and_then(|x| execute(x))
+        self.generate_by(&data)
     }
 
+    /// Generate a word by one of the `%` statements, chosen by their weights.
     pub fn generate_by(&self, data: &Data) -> Result<String, Error> {
-        data.get_statements_ref().and_then(|x| execute(x))
+        generate_word(data, None, self.mode, self.lexicon.as_ref(), self.rng().as_mut())
+    }
+
+    /// Generate a word by the `%name = ...;` statements of `name`.
+    pub fn generate_named(&self, data: &Data, name: &str) -> Result<String, Error> {
+        generate_word(data, Some(name), self.mode, self.lexicon.as_ref(), self.rng().as_mut())
+    }
+
+    /// Generate a value of the variable `name`, with its own excludes applied.
+    /// This always uses rejection sampling and does not check the lexicon.
+    pub fn generate_variable(&self, data: &Data, name: &str) -> Result<String, Error> {
+        let variables = data.get_statements_ref().map(|x| get_variables(x))?;
+        if !variables.contains_key(name) {
+            return Err(Error::NotFoundVariable(name.to_owned()));
+        }
+
+        let mut rng = self.rng();
+        let value = Value::Variable(name.to_owned());
+        let mut retry_count = 1;
+        loop {
+            let result = execute_value(&value, &variables, rng.as_mut()).map(|(x, _)| data.decode(&x));
+
+            // Only an excluded value is worth drawing again.
+            if !matches!(result, Err(Error::OverRetryCount)) { break result }
+
+            retry_count += 1;
+            if retry_count >= DEFAULT_RETRY_COUNT { break result }
+        }
+    }
+
+    /// Generate a word together with the derivation which produced it.
+    /// Derivations are only known to rejection sampling, so this ignores the sampling mode.
+    pub fn generate_traced(&self, data: &Data) -> Result<(String, Derivation), Error> {
+        let (word, derivation) = self.trace(data)?;
+        Ok((word, data.decode_derivation(&derivation)))
+    }
+
+    // The derivation still has the declared segments as single characters.
+    fn trace(&self, data: &Data) -> Result<(String, Derivation), Error> {
+        let mut rng = self.rng();
+        let scopes = data.get_statements_ref().and_then(|x| get_generate_scopes(x, None))?;
+        let filter = WordFilter::new(data, self.lexicon.as_ref());
+
+        execute(&scopes, &filter, rng.as_mut())
+    }
+
+    /// Generate a word and spell it by each of the maps in `names`, in the same order.
+    pub fn generate_rendered(&self, data: &Data, names: &[&str]) -> Result<(String, Vec<String>), Error> {
+        if let Some(name) = names.iter().find(|x| !data.map_names().contains(x)) {
+            return Err(Error::NotFoundMap(name.to_string()));
+        }
+
+        let word = self.generate_by(data)?;
+        let renderings = names.iter().map(|x| data.render(&word, x)).collect::<Result<Vec<String>, Error>>()?;
+        Ok((word, renderings))
+    }
+
+    /// Generate a word split into the syllables marked by `@syllable`.
+    /// Syllables are taken from the derivation, so they hold the text before `rewrite` rules.
+    pub fn generate_syllables(&self, data: &Data) -> Result<Word, Error> {
+        let (text, derivation) = self.trace(data)?;
+        // stress is chosen like it was marked, where a declared segment is one character.
+        let stress = data.get_stress().and_then(|x| x.choose(&data.syllabify(&derivation)));
+        let syllables = data.syllabify(&data.decode_derivation(&derivation));
+
+        Ok(Word { text, syllables, stress })
     }
 
     pub fn generate_many(&self, text: &str, count: u32) -> Vec<Result<String, Error>> {
@@ -46,85 +181,317 @@ impl Zatlin {
         let mut result = vec![];
         let mut i = 0;
         while i < count {
-            result.push(data.get_statements_ref().and_then(|x| execute(x)));
+            result.push(self.generate_by(data));
             i += 1;
         }
 
         result
     }
 
+    /// Generate `count` words in parallel.
+    /// Each word uses its own `StdRng` seeded from this generator, so a seeded generator gives
+    /// the same result regardless of the number of threads (but not the same as `generate_many_by`).
+    #[cfg(feature = "rayon")]
+    pub fn par_generate_many_by(&self, data: &Data, count: u32) -> Vec<Result<String, Error>> {
+        use rayon::prelude::*;
+
+        let seeds: Vec<u64> = {
+            let mut rng = self.rng();
+            (0..count).map(|_| rng.next_u64()).collect()
+        };
+
+        let (mode, lexicon) = (self.mode, self.lexicon.as_ref());
+        seeds.into_par_iter()
+            .map(|seed| generate_word(data, None, mode, lexicon, &mut StdRng::seed_from_u64(seed)))
+            .collect()
+    }
+
+    /// An endless iterator which generates a word on every `next`.
+    pub fn iter<'a>(&'a self, data: &'a Data) -> Words<'a> {
+        Words { zatlin: self, data }
+    }
+
+    /// Generate `count` distinct words.
+    /// If the grammar cannot supply them, `Error::Exhausted` carries the distinct words which were found.
+    ///
+    /// When `Data::enumerate` can analyse the grammar, the exhaustion is exact.
+    /// Otherwise it is a heuristic: the words are drawn until `set_unique_miss_limit` draws in a row
+    /// give no new word, so a large grammar with a skewed distribution can be reported as exhausted early.
+    pub fn generate_unique(&self, data: &Data, count: usize) -> Result<Vec<String>, Error> {
+        // When the grammar is finite and small enough, every word is needed anyway.
+        // The count is not known for grammars with rewrite rules, stress marks, length constraints or normalization.
+        if let Ok(words) = data.enumerate() {
+            if words.total() <= BigUint::from(count) {
+                let mut result: Vec<String> = words
+                    .filter(|x| !self.lexicon.as_ref().is_some_and(|lexicon| lexicon.is_rejected(x)))
+                    .collect();
+                result.shuffle(self.rng().as_mut());
+
+                return if result.len() < count { Err(Error::Exhausted(result)) } else { Ok(result) };
+            }
+        }
+
+        let mut result: Vec<String> = Vec::new();
+        let mut found: HashSet<String> = HashSet::new();
+        let mut miss_count = 0;
+        while result.len() < count {
+            match self.generate_by(data) {
+                Ok(word) if !found.contains(&word) => {
+                    found.insert(word.clone());
+                    result.push(word);
+                    miss_count = 0;
+                },
+                Ok(_) | Err(Error::OverRetryCount) => {
+                    miss_count += 1;
+                    if miss_count >= self.unique_miss_limit {
+                        return Err(Error::Exhausted(result));
+                    }
+                },
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(result)
+    }
+
     pub fn create_data(text: &str) -> Result<Data, Error> {
         Data::try_from(text)
     }
 }
 
+/// Iterator returned by `Zatlin::iter`. It never ends.
+pub struct Words<'a> {
+    zatlin: &'a Zatlin,
+    data: &'a Data,
+}
+
+impl Iterator for Words<'_> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.zatlin.generate_by(self.data))
+    }
+}
+
+fn generate_word(data: &Data, name: Option<&str>, mode: SamplingMode, lexicon: Option<&Lexicon>, rng: &mut dyn RngCore) -> Result<String, Error> {
+    // Stress and the number of syllables come from the derivation, which only rejection sampling knows.
+    match mode {
+        SamplingMode::Rejection => {
+            let scopes = data.get_statements_ref().and_then(|x| get_generate_scopes(x, name))?;
+            let filter = WordFilter::new(data, lexicon);
+            execute(&scopes, &filter, rng).map(|(word, _)| word)
+        },
+        SamplingMode::Compiled if data.needs_derivation() => generate_word(data, name, SamplingMode::Rejection, lexicon, rng),
+        SamplingMode::Compiled => {
+            // Grammars which can not be compiled (recursive, too large or with excludes which a DFA can not express)
+            // are sampled by rejection, which also reports the errors of invalid grammars.
+            let sampler = match data.get_sampler(name) {
+                Ok(sampler) => sampler,
+                Err(_) => return generate_word(data, name, SamplingMode::Rejection, lexicon, rng),
+            };
+            let filter = WordFilter::new(data, lexicon);
+
+            // The sampler does not know the lexicon and lengths (nor the excludes of generate, when rules
+            // rewrite the word before them), so those are still retried.
+            for _ in 0..DEFAULT_RETRY_COUNT {
+                let (word, excludes) = sampler.sample(rng)?;
+                if let Some(word) = filter.apply(&word, excludes, None) {
+                    return Ok(word);
+                }
+            }
+            Err(Error::OverRetryCount)
+        },
+    }
+}
+
 #[derive(Debug, Clone)]
 struct VariableData {
-    pub expression: Rc<Expression>,
+    pub expression: Arc<Expression>,
 }
 
 impl VariableData {
-    pub fn new(expression: &Rc<Expression>) -> Self {
+    pub fn new(expression: &Arc<Expression>) -> Self {
         Self {
-            expression: Rc::clone(&expression),
+            expression: Arc::clone(expression),
         }
     }
 }
 
-fn execute(operators: &Vec<Statement>) -> Result<String, Error> {
+#[derive(Debug, Clone)]
+struct GenerateScope {
+    weight: f64,
+    generate: VariableData,
+    variables: HashMap<String, VariableData>,
+}
+
+fn get_variables(operators: &[Statement]) -> HashMap<String, VariableData> {
     let mut variables: HashMap<String, VariableData> = HashMap::new();
 
+    for operator in operators.iter() {
+        if let Statement::Define(DefineStruct { name: key, expr }) = operator {
+            variables.insert(key.to_string(), VariableData::new(expr));
+        }
+    }
+
+    variables
+}
+
+// Every generate statement with the variables defined before it.
+// When `name` is given, only the generate statements of that name.
+fn get_generate_scopes(operators: &[Statement], name: Option<&str>) -> Result<Vec<GenerateScope>, Error> {
+    let mut variables: HashMap<String, VariableData> = HashMap::new();
+    let mut scopes: Vec<GenerateScope> = Vec::new();
+
+    for operator in operators.iter() {
+        match operator {
+            Statement::Define(DefineStruct { name: key, expr }) => {
+                let data = VariableData::new(expr);
+                variables.insert(key.to_string(), data);
+            },
+            Statement::Generate(GenerateStruct { name: generate_name, weight, expr }) => {
+                if name.is_none() || name == generate_name.as_deref() {
+                    scopes.push(GenerateScope { weight: *weight, generate: VariableData::new(expr), variables: variables.clone() });
+                }
+            },
+            Statement::Import(_) | Statement::Rewrite(_) | Statement::Map(_) | Statement::Syllable(_) | Statement::Stress(_) | Statement::Length(_) | Statement::Segments(_) => {},
+        };
+    }
+
+    match name {
+        Some(name) if scopes.is_empty() => Err(Error::NotFoundGenerate(name.to_owned())),
+        _ => Ok(scopes),
+    }
+}
+
+// What is applied to the whole word of a generate statement.
+struct WordFilter<'a> {
+    rules: Vec<&'a RewriteStruct>,
+    stress: Option<&'a StressStruct>,
+    lengths: Vec<&'a LengthStruct>,
+    roles: HashMap<&'a str, SyllableRole>,
+    segments: Option<&'a SegmentsStruct>,
+    normalization: Normalization,
+    lexicon: Option<&'a Lexicon>,
+}
+
+impl<'a> WordFilter<'a> {
+    fn new(data: &'a Data, lexicon: Option<&'a Lexicon>) -> Self {
+        Self {
+            rules: data.get_rules(),
+            stress: data.get_stress(),
+            lengths: data.get_lengths(),
+            roles: data.get_roles(),
+            segments: data.get_segments(),
+            normalization: data.get_normalization(),
+            lexicon,
+        }
+    }
+
+    // The rewritten and decoded word, or None when it is excluded, has a wrong length or collides with the lexicon.
+    // Stress is marked before the rules and excludes, so they can refer to it.
+    fn apply(&self, word: &str, excludes: Option<&Exclude>, derivation: Option<&Derivation>) -> Option<String> {
+        let syllables = derivation.map(|x| syllable::locate(x, &self.roles, 0));
+        let (word, mark) = match (self.stress, &syllables) {
+            (Some(stress), Some(syllables)) => {
+                let marked = stress.mark(word, syllables);
+                let mark = (marked.len() > word.len()).then_some(stress.mark.as_str());
+                (marked, mark)
+            },
+            _ => (word.to_owned(), None),
+        };
+
+        let word = self.normalization.apply(&rewrite::rewrite(&self.rules, word, false));
+        if excludes.is_some_and(|x| contains_excludes(x, &word)) {
+            return None;
+        }
+
+        let word = rewrite::rewrite(&self.rules, word, true);
+        // Lengths do not count the stress mark. An acute mark composed by NFC adds no character.
+        let unmarked = |text: &str| mark.map_or_else(|| text.to_owned(), |x| text.replacen(x, "", 1));
+        let segments = unmarked(&word).chars().count();
+        let word = self.normalization.apply(&self.segments.map_or(word.clone(), |x| x.decode(&word)));
+        if !self.lengths.iter().all(|x| x.accepts(&unmarked(&word), segments, syllables.as_ref().map(|x| x.len()))) {
+            return None;
+        }
+        if self.lexicon.is_some_and(|x| x.is_rejected(&word)) {
+            return None;
+        }
+
+        Some(word)
+    }
+}
+
+// The derivation keeps the word before rewriting.
+fn execute(scopes: &[GenerateScope], filter: &WordFilter, rng: &mut dyn RngCore) -> Result<(String, Derivation), Error> {
+    if scopes.is_empty() {
+        return Ok((String::default(), Derivation::new(DerivationKind::Generate, 0, String::default(), vec![])));
+    }
+
+    let max: f64 = scopes.iter().map(|x| x.weight).filter(|x| *x > 0.0).sum();
+    if max <= 0.0 {
+        return Err(Error::NotFoundPattern);
+    }
+
     let mut retry_count = 1;
     loop {
-        let mut result: Result<String, Error> = Ok(String::default());
-
-        for operator in operators.iter() {
-            match operator {
-                Statement::Define(DefineStruct { name: key, expr }) => {
-                    let data = VariableData::new(expr);
-                    variables.insert(key.to_string(), data);
-                },
-                Statement::Generate(expr) => {
-                    result = execute_expression(&VariableData::new(expr), &variables);
-                    break;
-                },
-            };
+        // The generate statement is chosen again on every retry, like the patterns in it.
+        let value = rng.gen_range(0.0..max);
+        let mut sum = 0.0;
+        let mut scope = &scopes[0];
+        for item in scopes.iter().filter(|x| x.weight > 0.0) {
+            sum += item.weight;
+            scope = item;
+            if value < sum {
+                break;
+            }
         }
 
-        if matches!(result, Ok(_)) { break result }
+        let result = execute_patterns(&scope.generate, DerivationKind::Generate, &scope.variables, rng).and_then(|x| {
+            match filter.apply(&x.text, Some(&scope.generate.expression.excludes), Some(&x)) {
+                Some(word) => Ok((word, x)),
+                None => Err(Error::OverRetryCount),
+            }
+        });
+
+        if result.is_ok() { break result }
 
         retry_count += 1;
         if retry_count >= DEFAULT_RETRY_COUNT { break result }
     }
 }
 
-fn execute_expression(data: &VariableData, variables: &HashMap<String, VariableData>) -> Result<String, Error> {
-    let max: f64 = data.expression.patterns.iter().map(|x| x.count).sum();
-    let mut random = rand::thread_rng();
+fn execute_expression(data: &VariableData, kind: DerivationKind, variables: &HashMap<String, VariableData>, rng: &mut dyn RngCore) -> Result<Derivation, Error> {
+    let derivation = execute_patterns(data, kind, variables, rng)?;
+
+    if !contains_excludes(&data.expression.excludes, &derivation.text) {
+        Ok(derivation)
+    } else {
+        Err(Error::OverRetryCount)
+    }
+}
 
-    let value = random.gen_range(0.0..max);
+// Choose a pattern by the counts and execute it, without checking the excludes.
+fn execute_patterns(data: &VariableData, kind: DerivationKind, variables: &HashMap<String, VariableData>, rng: &mut dyn RngCore) -> Result<Derivation, Error> {
+    let max: f64 = data.expression.patterns.iter().map(|x| x.count).sum();
+    let value = rng.gen_range(0.0..max);
 
     let mut sum = 0.0;
-    let mut pattern: Option<&Pattern> = None;
-    for item in data.expression.patterns.iter() {
-        sum = sum + item.count;
+    let mut pattern: Option<(usize, &Pattern)> = None;
+    for (index, item) in data.expression.patterns.iter().enumerate() {
+        sum += item.count;
         if value < sum {
-            pattern = Some(item);
+            pattern = Some((index, item));
             break;
         }
     }
 
-    let pattern = match pattern {
+    let (index, pattern) = match pattern {
         Some(v) => v,
         None => return Err(Error::NotFoundPattern),
     };
-    let result = execute_pattern(&pattern, &variables)?;
+    let (result, children) = execute_pattern(pattern, variables, rng)?;
 
-    if !contains_excludes(&data.expression.excludes, &result) {
-        Ok(result)
-    } else {
-        Err(Error::OverRetryCount)
-    }
+    Ok(Derivation::new(kind, index, result, children))
 }
 
 fn contains_excludes(excludes: &Exclude, result: &str) -> bool {
@@ -135,31 +502,60 @@ fn contains_excludes(excludes: &Exclude, result: &str) -> bool {
     }
 }
 
-fn execute_pattern(pattern: &Pattern, variables: &HashMap<String, VariableData>) -> Result<String, Error> {
-    let mut result = String::default();
+fn execute_pattern(pattern: &Pattern, variables: &HashMap<String, VariableData>, rng: &mut dyn RngCore) -> Result<(String, Vec<Derivation>), Error> {
+    let mut matched: Vec<String> = Vec::default();
+    let mut children: Vec<Derivation> = Vec::default();
 
     for item in pattern.values.iter() {
-        let value = execute_value(&item, &variables)?;
-        result = result + &value;
+        let value = if let Value::Backreference(index) = item {
+            matched.get(*index).cloned().ok_or_else(|| Error::ErrorMessage(format!("Invalid backreference: &{}", index + 1), None))?
+        } else {
+            match execute_value(item, variables, rng)? {
+                (value, Some(child)) => {
+                    children.push(child.at(matched.iter().map(|x| x.len()).sum()));
+                    value
+                },
+                (value, None) => value,
+            }
+        };
+        matched.push(value);
     }
 
-    Ok(result)
+    Ok((matched.concat(), children))
 }
 
-fn execute_value(value: &Value, variables: &HashMap<String, VariableData>) -> Result<String, Error> {
-    match value {
+fn execute_value(value: &Value, variables: &HashMap<String, VariableData>, rng: &mut dyn RngCore) -> Result<(String, Option<Derivation>), Error> {
+    let derivation = match value {
         Value::Variable(key) => {
             if let Some(data) = variables.get(key) {
-                execute_expression(&data, &variables)
+                execute_expression(data, DerivationKind::Variable(key.to_owned()), variables, rng)?
             } else {
-                Err(Error::NotFoundVariable(key.to_owned()))
+                return Err(Error::NotFoundVariable(key.to_owned()))
             }
         },
-        Value::Literal(val) => Ok(val.to_owned()),
+        Value::Literal(val) => return Ok((val.to_owned(), None)),
         Value::InnerPattern(patterns) => {
-            let expr = Rc::new(Expression { patterns: patterns.to_owned(), excludes: Exclude::Pattern(Vec::default()) });
+            let expr = Arc::new(Expression { patterns: patterns.to_owned(), excludes: Exclude::Pattern(Vec::default()) });
             let data = VariableData::new(&expr);
-            execute_expression(&data, &variables)
+            execute_expression(&data, DerivationKind::InnerPattern, variables, rng)?
         },
-    }
+        Value::Backreference(index) => return Err(Error::ErrorMessage(format!("Invalid backreference: &{}", index + 1), None)),
+        Value::Regex(pattern) => return Err(Error::ErrorMessage(format!("Regex can only be used in excludes: /{}/", pattern), None)),
+        // Every number of repetitions is equally likely.
+        Value::Repeat(value, min, max) => {
+            let count = rng.gen_range(*min..=*max);
+
+            let mut text = String::default();
+            let mut children: Vec<Derivation> = Vec::default();
+            for _ in 0..count {
+                let (value, child) = execute_value(value, variables, rng)?;
+                children.extend(child.map(|x| x.at(text.len())));
+                text.push_str(&value);
+            }
+
+            Derivation::new(DerivationKind::Repeat, count, text, children)
+        },
+    };
+
+    Ok((derivation.text.clone(), Some(derivation)))
 }
@@ -0,0 +1,98 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalization of the literals of a grammar and of the words generated by it,
+/// so that precomposed and decomposed characters are matched alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    #[default]
+    None,
+    Nfc,
+    Nfd,
+}
+
+impl Normalization {
+    pub(crate) fn apply(&self, text: &str) -> String {
+        match self {
+            Self::None => text.to_owned(),
+            Self::Nfc => text.nfc().collect(),
+            Self::Nfd => text.nfd().collect(),
+        }
+    }
+
+    // Only the literal text of a regex is normalized. A character class matches single characters,
+    // so its members are kept as written, and a class can not match a precomposed member in NFD text.
+    // None when the class has such a member.
+    pub(crate) fn apply_regex(&self, pattern: &str) -> Option<String> {
+        if *self == Self::None {
+            return Some(pattern.to_owned());
+        }
+
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut result = String::default();
+        let mut literal = String::default();
+        let mut depth = 0;
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            let end = match c {
+                // an escape is kept with its argument, like `\u{e1}` or `\pL`.
+                '\\' => match chars.get(index + 1) {
+                    Some('x' | 'u' | 'U' | 'p' | 'P') if chars.get(index + 2) == Some(&'{') => find(&chars, index + 2, '}'),
+                    Some(_) => index + 2,
+                    None => index + 1,
+                },
+                '[' => {
+                    depth += 1;
+                    // `]` right after the opening bracket is a member.
+                    let mut end = index + 1;
+                    if chars.get(end) == Some(&'^') { end += 1; }
+                    if chars.get(end) == Some(&']') { end += 1; }
+                    end
+                },
+                ']' if depth > 0 => {
+                    depth -= 1;
+                    index + 1
+                },
+                '{' if depth == 0 => find(&chars, index, '}'),
+                // the name of a group is kept, too.
+                '(' if depth == 0 && (chars[index + 1..].starts_with(&['?', 'P', '<']) || chars[index + 1..].starts_with(&['?', '<'])) => find(&chars, index, '>'),
+                '(' | ')' | '|' | '.' | '^' | '$' | '?' | '*' | '+' if depth == 0 => index + 1,
+                _ if depth > 0 => {
+                    if *self == Self::Nfd && self.apply(&c.to_string()).chars().count() > 1 {
+                        return None;
+                    }
+                    index + 1
+                },
+                _ => {
+                    literal.push(c);
+                    index += 1;
+                    continue;
+                },
+            };
+
+            result.push_str(&self.apply_literal(&literal));
+            literal.clear();
+            result.extend(&chars[index..end.min(chars.len())]);
+            index = end;
+        }
+        result.push_str(&self.apply_literal(&literal));
+
+        Some(result)
+    }
+
+    // A decomposed character is grouped, so that a quantifier after it still applies to all of it.
+    fn apply_literal(&self, text: &str) -> String {
+        match self {
+            Self::Nfd => text.chars().map(|x| {
+                let decomposed = self.apply(&x.to_string());
+                if decomposed.chars().count() > 1 { format!("(?:{})", decomposed) } else { decomposed }
+            }).collect(),
+            _ => self.apply(text),
+        }
+    }
+}
+
+// Index after the first `close` from `start`, or the end of the text.
+fn find(chars: &[char], start: usize, close: char) -> usize {
+    chars[start..].iter().position(|x| *x == close).map_or(chars.len(), |x| start + x + 1)
+}
@@ -0,0 +1,32 @@
+use crate::parser::MapStruct;
+
+impl MapStruct {
+    // At each position the longest matching entry is replaced, and characters
+    // which no entry matches are kept as they are.
+    pub(crate) fn render(&self, word: &str) -> String {
+        let mut result = String::default();
+        let mut rest = word;
+
+        while let Some(c) = rest.chars().next() {
+            let entry = self.entries.iter()
+                .filter(|(from, _)| rest.starts_with(from.as_str()))
+                .fold(None, |longest: Option<&(String, String)>, x| match longest {
+                    Some(longest) if longest.0.len() >= x.0.len() => Some(longest),
+                    _ => Some(x),
+                });
+
+            match entry {
+                Some((from, to)) => {
+                    result.push_str(to);
+                    rest = &rest[from.len()..];
+                },
+                None => {
+                    result.push(c);
+                    rest = &rest[c.len_utf8()..];
+                },
+            }
+        }
+
+        result
+    }
+}
@@ -1,22 +1,161 @@
 
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use regex::Regex;
 
 use crate::lexer::{TokenType, Token};
 use crate::error::Error;
 
+const MAX_EXPANSION: usize = 10_000;
+
 #[derive(Debug, Clone)]
 pub(crate) enum Statement {
     Define(DefineStruct),
-    Generate(Rc<Expression>)
+    Generate(GenerateStruct),
+    Import(ImportStruct),
+    Rewrite(RewriteStruct),
+    Map(MapStruct),
+    Syllable(SyllableStruct),
+    Stress(StressStruct),
+    Length(LengthStruct),
+    Segments(SegmentsStruct),
+}
+
+// `@segments "sh" "ts";`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SegmentsStruct {
+    pub segments: Vec<String>,
+}
+
+// `@length syllables {2,4};`
+#[derive(Debug, Clone)]
+pub(crate) struct LengthStruct {
+    pub unit: LengthUnit,
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LengthUnit {
+    Chars,
+    Graphemes,
+    Segments,
+    Syllables,
+}
+
+// `@stress final heavy | penult as ipa;`
+#[derive(Debug, Clone)]
+pub(crate) struct StressStruct {
+    pub alternatives: Vec<(StressPosition, Option<bool>)>,
+    pub mark: StressMark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StressPosition {
+    Initial,
+    Second,
+    Antepenult,
+    Penult,
+    Final,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StressMark {
+    // `ˈ` before the stressed syllable.
+    Ipa,
+    // U+0301 after the first character of the nucleus.
+    Acute,
+}
+
+// `@syllable S;`, `@onset C Cx;`, `@nucleus V;` and `@coda Ce;`
+#[derive(Debug, Clone)]
+pub(crate) struct SyllableStruct {
+    pub role: SyllableRole,
+    pub variables: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SyllableRole {
+    Syllable,
+    Onset,
+    Nucleus,
+    Coda,
+}
+
+// `map name { "from" -> "to"; ... }`
+#[derive(Debug, Clone)]
+pub(crate) struct MapStruct {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+// `rewrite target -> "replacement" when left _ right after exclude;`
+#[derive(Debug, Clone)]
+pub(crate) struct RewriteStruct {
+    pub target: Regex,
+    pub replacement: String,
+    pub left: Option<Regex>,
+    pub right: Option<Regex>,
+    pub after_exclude: bool,
+    pub source: RewriteSource,
+}
+
+// The target and contexts as written, so that `@segments` can encode literals before they are escaped.
+#[derive(Debug, Clone)]
+pub(crate) struct RewriteSource {
+    pub target: RewritePattern,
+    pub left: Option<RewritePattern>,
+    pub right: Option<RewritePattern>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum RewritePattern {
+    Literal(String),
+    Regex(String),
+}
+
+impl RewritePattern {
+    fn to_regex(&self) -> String {
+        match self {
+            Self::Literal(text) => regex::escape(text),
+            Self::Regex(pattern) => pattern.clone(),
+        }
+    }
+}
+
+impl RewriteStruct {
+    pub(crate) fn new(source: RewriteSource, replacement: String, after_exclude: bool, index: Option<usize>) -> Result<Self, Error> {
+        let build = |pattern: String| Regex::new(&pattern).map_err(|_| Error::ErrorMessage(format!("Invalid rewrite: {}", pattern), index));
+
+        Ok(Self {
+            target: build(format!("^(?:{})", source.target.to_regex()))?,
+            replacement,
+            left: source.left.as_ref().map(|x| build(format!("(?:{})$", x.to_regex()))).transpose()?,
+            right: source.right.as_ref().map(|x| build(format!("^(?:{})", x.to_regex()))).transpose()?,
+            after_exclude,
+            source,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ImportStruct {
+    pub path: String,
+    pub namespace: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct GenerateStruct {
+    pub name: Option<String>,
+    pub weight: f64,
+    pub expr: Arc<Expression>
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct DefineStruct {
     pub name: String,
-    pub expr: Rc<Expression>
+    pub expr: Arc<Expression>
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +170,24 @@ pub(crate) enum Exclude {
     Regex(Regex),
 }
 
+// Weights of the patterns by their positions, multiplied to their counts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Distribution {
+    Uniform,
+    Zipf(f64),
+    Geometric(f64),
+}
+
+impl Distribution {
+    fn weight(&self, rank: usize) -> f64 {
+        match self {
+            Self::Uniform => 1.0,
+            Self::Zipf(exponent) => (rank as f64).powf(-exponent),
+            Self::Geometric(probability) => probability * (1.0 - probability).powi(rank as i32 - 1),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Pattern {
     pub values: Vec<Value>,
@@ -50,6 +207,25 @@ impl Pattern {
     fn new(values: Vec<Value>, count: f64, mode: ExtractMode) -> Self {
         Self { values, count, mode }
     }
+
+    // Follow backreferences which point to another backreference.
+    pub(crate) fn get_referred_index(&self, index: usize) -> usize {
+        match self.values.get(index) {
+            Some(Value::Backreference(target)) if *target < index => self.get_referred_index(*target),
+            _ => index,
+        }
+    }
+
+    pub(crate) fn get_referred_indexes(&self) -> Vec<usize> {
+        let mut referred: Vec<usize> = self.values.iter().enumerate()
+            .filter(|(_, x)| matches!(x, Value::Backreference(_)))
+            .map(|(index, _)| self.get_referred_index(index))
+            .collect();
+        referred.sort_unstable();
+        referred.dedup();
+
+        referred
+    }
 }
 
 impl Exclude {
@@ -66,9 +242,22 @@ pub(crate) enum Value {
     Literal(String),
     Variable(String),
     InnerPattern(Vec<Pattern>),
+    Backreference(usize),
+    // The value repeated between min and max times (inclusive).
+    Repeat(Box<Value>, usize, usize),
+    // `/regex/`, only in excludes.
+    Regex(String),
 }
 
-pub(crate) fn parse(tokens: &Vec<Token>) -> Result<Vec<Statement>, Error> {
+pub(crate) fn parse(tokens: &[Token]) -> Result<Vec<Statement>, Error> {
+    for token in tokens.iter() {
+        if let TokenType::Regex(pattern) = &token.tokentype {
+            if Regex::new(pattern).is_err() {
+                return Err(Error::InvalidRegex(pattern.clone(), token.row, token.column))
+            }
+        }
+    }
+
     let mut statements = vec![];
     
     let mut index = 0;
@@ -76,21 +265,56 @@ pub(crate) fn parse(tokens: &Vec<Token>) -> Result<Vec<Statement>, Error> {
     while index < length {
         if let Some(value) = tokens.get(index) {
             match &value.tokentype {
+                TokenType::Variable(value) if value == "rewrite" && tokens.get(index + 1).map(|x| &x.tokentype) != Some(&TokenType::Equal) => {
+                    let (rewrite, next_index) = parse_rewrite(tokens, index + 1)?;
+                    statements.push(rewrite);
+                    index = next_index;
+                },
+                TokenType::Variable(value) if value == "map" && tokens.get(index + 1).map(|x| &x.tokentype) != Some(&TokenType::Equal) => {
+                    let (map, next_index) = parse_map(tokens, index + 1)?;
+                    statements.push(map);
+                    index = next_index;
+                },
                 TokenType::Variable(value) => {
-                    let (define, next_index) = parse_define(&value, &tokens, index + 1)?;
+                    let (define, next_index) = parse_define(value, tokens, index + 1)?;
                     statements.push(define);
                     index = next_index;
                 },
                 TokenType::Percent => {
-                    let (generate, next_index) = parse_generate(&tokens, index + 1)?;
+                    let (generate, next_index) = parse_generate(tokens, index + 1)?;
                     statements.push(generate);
                     index = next_index;
                 },
+                TokenType::Directive(name) if name == "import" => {
+                    let (import, next_index) = parse_import(tokens, index + 1)?;
+                    statements.push(import);
+                    index = next_index;
+                },
+                TokenType::Directive(name) if matches!(name.as_str(), "syllable" | "onset" | "nucleus" | "coda") => {
+                    let (syllable, next_index) = parse_syllable(name, tokens, index + 1)?;
+                    statements.push(syllable);
+                    index = next_index;
+                },
+                TokenType::Directive(name) if name == "segments" => {
+                    let (segments, next_index) = parse_segments(tokens, index + 1)?;
+                    statements.push(segments);
+                    index = next_index;
+                },
+                TokenType::Directive(name) if name == "length" => {
+                    let (length, next_index) = parse_length(tokens, index + 1)?;
+                    statements.push(length);
+                    index = next_index;
+                },
+                TokenType::Directive(name) if name == "stress" => {
+                    let (stress, next_index) = parse_stress(tokens, index + 1)?;
+                    statements.push(stress);
+                    index = next_index;
+                },
                 TokenType::Unknown(value) => {
                     return Err(Error::UnknownToken(value.clone(), index))
                 },
                 TokenType::NewLine => {
-                    index = index + 1
+                    index += 1
                 }
                 _ => {
                     return Err(Error::InvalidToken(String::from("statement"), value.to_string(), index))
@@ -101,12 +325,13 @@ pub(crate) fn parse(tokens: &Vec<Token>) -> Result<Vec<Statement>, Error> {
         }
     }
     
-    convert_statement_exclude(statements)
+    Ok(statements)
 }
 
 
 
-fn convert_statement_exclude(statements: Vec<Statement>) -> Result<Vec<Statement>, Error> {
+// Run after `@import` statements are resolved.
+pub(crate) fn convert_statement_exclude(statements: Vec<Statement>) -> Result<Vec<Statement>, Error> {
     let mut exclude_regex = HashMap::default();
     let mut updated_statements: Vec<Statement> = Vec::default();
 
@@ -116,10 +341,18 @@ fn convert_statement_exclude(statements: Vec<Statement>) -> Result<Vec<Statement
                 let mut used_variables: Vec<String> = Vec::default();
                 convert_define_exclude(def_statement, &statements, &mut exclude_regex, &mut used_variables)
             },
-            Statement::Generate(expression) => {
+            Statement::Generate(gen_statement) => {
                 let mut used_variables: Vec<String> = Vec::default();
-                convert_generate_exclude(Rc::clone(expression), &statements, &mut exclude_regex, &mut used_variables)
+                convert_generate_exclude(gen_statement, &statements, &mut exclude_regex, &mut used_variables)
+            },
+            Statement::Import(import) => Err(Error::ErrorMessage(format!("Unresolved import: {}", import.path), None)),
+            Statement::Syllable(syllable) => {
+                match syllable.variables.iter().find(|x| !statements.iter().any(|y| matches!(y, Statement::Define(define) if &define.name == *x))) {
+                    Some(name) => Err(Error::NotFoundVariable(name.clone())),
+                    None => Ok(statement.clone()),
+                }
             },
+            Statement::Rewrite(_) | Statement::Map(_) | Statement::Stress(_) | Statement::Length(_) | Statement::Segments(_) => Ok(statement.clone()),
         }?;
 
         updated_statements.push(updated_statement);
@@ -128,15 +361,15 @@ fn convert_statement_exclude(statements: Vec<Statement>) -> Result<Vec<Statement
     Ok(updated_statements)
 }
 
-fn convert_generate_exclude(expression: Rc<Expression>, statements: &[Statement], exclude_regex: &mut HashMap<String, Regex>, used_variables: &mut Vec<String>) -> Result<Statement, Error> {
-    if expression.excludes.is_empty() {
-        Ok(Statement::Generate(expression))
+fn convert_generate_exclude(gen_statement: &GenerateStruct, statements: &[Statement], exclude_regex: &mut HashMap<String, Regex>, used_variables: &mut Vec<String>) -> Result<Statement, Error> {
+    if gen_statement.expr.excludes.is_empty() {
+        Ok(Statement::Generate(gen_statement.clone()))
     } else {
-        let expr = expression.as_ref();
+        let expr = gen_statement.expr.as_ref();
         let patterns = expr.patterns.clone();
         let excludes = convert_exclude(&expr.excludes, statements, exclude_regex, used_variables)?;
         
-        Ok(Statement::Generate(Rc::new(Expression { patterns, excludes })))
+        Ok(Statement::Generate(GenerateStruct { name: gen_statement.name.clone(), weight: gen_statement.weight, expr: Arc::new(Expression { patterns, excludes }) }))
     }
 }
 
@@ -148,7 +381,7 @@ fn convert_define_exclude(def_statement: &DefineStruct, statements: &[Statement]
         let patterns = expr.patterns.clone();
         let excludes = convert_exclude(&expr.excludes, statements, exclude_regex, used_variables)?;
     
-        Ok(Statement::Define(DefineStruct { name: def_statement.name.clone(), expr: Rc::new(Expression { patterns, excludes }) }))
+        Ok(Statement::Define(DefineStruct { name: def_statement.name.clone(), expr: Arc::new(Expression { patterns, excludes }) }))
     }
 }
 
@@ -171,6 +404,10 @@ fn convert_exclude(excludes: &Exclude, statements: &[Statement], exclude_regex:
 }
 
 fn convert_pattern(pattern: &Pattern, statements: &[Statement], exclude_regex: &mut HashMap<String, Regex>, used_variables: &mut Vec<String>, anonymous_pattern: bool) -> Result<String, Error> {
+    if pattern.values.iter().any(|x| matches!(x, Value::Backreference(_))) {
+        return convert_backreference_pattern(pattern, statements, exclude_regex, used_variables, anonymous_pattern);
+    }
+
     let mut pattern_str = String::default();
     let values = convert_from_values(&pattern.values, statements, exclude_regex, used_variables, anonymous_pattern)?;
 
@@ -198,6 +435,134 @@ fn convert_pattern(pattern: &Pattern, statements: &[Statement], exclude_regex: &
     Ok(pattern_str)
 }
 
+// regex cannot express backreferences, so the referred values are expanded
+// into every string they can produce and each combination becomes one alternative.
+fn convert_backreference_pattern(pattern: &Pattern, statements: &[Statement], exclude_regex: &mut HashMap<String, Regex>, used_variables: &mut Vec<String>, anonymous_pattern: bool) -> Result<String, Error> {
+    let referred = pattern.get_referred_indexes();
+
+    let mut candidates: Vec<Vec<String>> = Vec::default();
+    for index in referred.iter() {
+        let value = pattern.values.get(*index).ok_or_else(|| Error::ErrorMessage(format!("Invalid backreference: &{}", index + 1), None))?;
+        candidates.push(expand_value(value, statements, used_variables)?);
+    }
+
+    let mut alternatives: Vec<String> = Vec::default();
+    for combination in cartesian_product(&candidates)? {
+        let values = pattern.values.iter().enumerate().map(|(index, value)| {
+            match referred.iter().position(|x| *x == pattern.get_referred_index(index)) {
+                Some(position) => Value::Literal(combination[position].clone()),
+                None => value.clone(),
+            }
+        }).collect();
+
+        let concrete = Pattern::new(values, pattern.count, pattern.mode.clone());
+        alternatives.push(convert_pattern(&concrete, statements, exclude_regex, used_variables, anonymous_pattern)?);
+    }
+
+    Ok(alternatives.join("|"))
+}
+
+fn expand_value(value: &Value, statements: &[Statement], used_variables: &mut Vec<String>) -> Result<Vec<String>, Error> {
+    match value {
+        Value::Literal(s) => Ok(vec![s.to_owned()]),
+        Value::Variable(v) => {
+            if used_variables.contains(v) {
+                return Err(Error::ErrorMessage(format!("Recursive variable in exclude: {}", v), None));
+            }
+            used_variables.push(v.clone());
+
+            let result = if let Some(Statement::Define(DefineStruct { name:_, expr })) = statements.iter().find(|x| if let Statement::Define(DefineStruct { name, expr: _ }) = x { name == v } else { false }) {
+                expand_patterns(&expr.patterns, statements, used_variables)
+            } else {
+                Err(Error::NotFoundVariable(v.to_owned()))
+            };
+            used_variables.pop();
+
+            result
+        },
+        Value::InnerPattern(patterns) => expand_patterns(patterns, statements, used_variables),
+        Value::Backreference(index) => Err(Error::ErrorMessage(format!("Invalid backreference: &{}", index + 1), None)),
+        Value::Regex(pattern) => Err(Error::ErrorMessage(format!("Backreference can not refer to regex: /{}/", pattern), None)),
+        // Only a repeat which is referred to is expanded; others become a regex quantifier.
+        Value::Repeat(value, min, max) => {
+            let candidates = expand_value(value, statements, used_variables)?;
+
+            let mut result: Vec<String> = Vec::default();
+            let mut found: HashSet<String> = HashSet::new();
+            let mut words: Vec<String> = vec![String::default()];
+            for count in 0..=*max {
+                if count >= *min {
+                    result.extend(words.iter().filter(|x| found.insert(x.to_string())).cloned());
+                    check_expansion(result.len())?;
+                }
+                if count < *max {
+                    let mut repeated: HashSet<String> = HashSet::new();
+                    words = cartesian_product(&[words, candidates.clone()])?.into_iter()
+                        .map(|x| x.concat())
+                        .filter(|x| repeated.insert(x.clone()))
+                        .collect();
+                }
+            }
+
+            Ok(result)
+        },
+    }
+}
+
+fn expand_patterns(patterns: &[Pattern], statements: &[Statement], used_variables: &mut Vec<String>) -> Result<Vec<String>, Error> {
+    let mut result: Vec<String> = Vec::default();
+    let mut found: HashSet<String> = HashSet::new();
+
+    for pattern in patterns.iter() {
+        let candidates = pattern.values.iter()
+            .filter(|x| !matches!(x, Value::Backreference(_)))
+            .map(|x| expand_value(x, statements, used_variables))
+            .collect::<Result<Vec<Vec<String>>, Error>>()?;
+
+        for combination in cartesian_product(&candidates)? {
+            let mut parts = combination.into_iter();
+            let mut matched: Vec<String> = Vec::default();
+            for value in pattern.values.iter() {
+                let part = match value {
+                    Value::Backreference(index) => matched[*index].clone(),
+                    _ => parts.next().unwrap_or_default(),
+                };
+                matched.push(part);
+            }
+
+            let word = matched.concat();
+            if found.insert(word.clone()) {
+                result.push(word);
+            }
+        }
+        check_expansion(result.len())?;
+    }
+
+    Ok(result)
+}
+
+fn cartesian_product(candidates: &[Vec<String>]) -> Result<Vec<Vec<String>>, Error> {
+    check_expansion(candidates.iter().fold(1, |count, x| count.saturating_mul(x.len())))?;
+
+    Ok(candidates.iter().fold(vec![vec![]], |result, candidate| {
+        result.iter().flat_map(|prefix| candidate.iter().map(move |x| {
+            let mut combination = prefix.clone();
+            combination.push(x.clone());
+            combination
+        })).collect()
+    }))
+}
+
+// Values referred to by backreferences in excludes are expanded into every string they can produce,
+// so a value which produces too many of them is rejected instead of building a huge regex.
+fn check_expansion(count: usize) -> Result<(), Error> {
+    if count > MAX_EXPANSION {
+        return Err(Error::ErrorMessage(format!("Backreference in exclude refers to more than {} strings", MAX_EXPANSION), None));
+    }
+
+    Ok(())
+}
+
 fn convert_from_values(values: &[Value], statements: &[Statement], exclude_regex: &mut HashMap<String, Regex>, used_variables: &mut Vec<String>, anonymous_pattern: bool) -> Result<Vec<String>, Error> {
     let mut values_str = Vec::default();
 
@@ -240,7 +605,7 @@ fn convert_from_values(values: &[Value], statements: &[Statement], exclude_regex
                     updated_excludes.push(convert_pattern(pattern, statements, exclude_regex, used_variables, false)?);
                 }
                 
-                let result = String::from(updated_excludes.join("|"));
+                let result = updated_excludes.join("|");
 
                 if result.contains("|") {
                     format!("({})", result)
@@ -248,6 +613,12 @@ fn convert_from_values(values: &[Value], statements: &[Statement], exclude_regex
                     result.to_string()
                 }
             },
+            Value::Backreference(index) => return Err(Error::ErrorMessage(format!("Invalid backreference: &{}", index + 1), None)),
+            Value::Regex(pattern) => format!("(?:{})", pattern),
+            Value::Repeat(value, min, max) => {
+                let result = convert_from_values(std::slice::from_ref(value.as_ref()), statements, exclude_regex, used_variables, anonymous_pattern)?.concat();
+                format!("(?:{}){{{},{}}}", result, min, max)
+            },
         };
 
         values_str.push(s);
@@ -268,11 +639,11 @@ fn parse_define(value: &str, tokens: &[Token], index: usize) -> Result<(Statemen
         return Err(Error::EndOfToken(String::from("define variable"), index))
     };
 
-    let (expr, next_index) = parse_expression(&tokens, next_index)?;
+    let (expr, next_index) = parse_expression(tokens, next_index)?;
 
     if let Some(token) = tokens.get(next_index) {
         if TokenType::Semicolon == token.tokentype || TokenType::NewLine == token.tokentype {
-            Ok((Statement::Define(DefineStruct { name: String::from(value), expr: Rc::new(expr) }), next_index + 1))
+            Ok((Statement::Define(DefineStruct { name: String::from(value), expr: Arc::new(expr) }), next_index + 1))
         } else {
             Err(Error::InvalidToken(String::from("expression of define variable"), token.to_string(), index))
         }
@@ -282,11 +653,12 @@ fn parse_define(value: &str, tokens: &[Token], index: usize) -> Result<(Statemen
 }
 
 fn parse_generate(tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
-    let (expr, next_index) = parse_expression(&tokens, index)?;
+    let (name, weight, next_index) = parse_generate_header(tokens, index);
+    let (expr, next_index) = parse_expression(tokens, next_index)?;
 
     if let Some(token) = tokens.get(next_index) {
         if TokenType::Semicolon == token.tokentype {
-            Ok((Statement::Generate(Rc::new(expr)), next_index + 1))
+            Ok((Statement::Generate(GenerateStruct { name, weight, expr: Arc::new(expr) }), next_index + 1))
         } else {
             Err(Error::InvalidToken(String::from("generate"), token.tokentype.to_string(), next_index))
         }
@@ -295,11 +667,275 @@ fn parse_generate(tokens: &[Token], index: usize) -> Result<(Statement, usize),
     }
 }
 
+fn parse_import(tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
+    let types: Vec<&TokenType> = tokens.iter().skip(index).take(4).map(|x| &x.tokentype).collect();
+
+    let (import, next_index) = match types.as_slice() {
+        [TokenType::Value(path), TokenType::Variable(keyword), TokenType::Variable(namespace), ..] if keyword == "as" => {
+            (ImportStruct { path: path.clone(), namespace: Some(namespace.clone()) }, index + 3)
+        },
+        [TokenType::Value(path), ..] => (ImportStruct { path: path.clone(), namespace: None }, index + 1),
+        [token, ..] => return Err(Error::InvalidToken(String::from("import"), token.to_string(), index)),
+        [] => return Err(Error::EndOfToken(String::from("import"), index)),
+    };
+
+    match tokens.get(next_index).map(|x| &x.tokentype) {
+        Some(TokenType::Semicolon) => Ok((Statement::Import(import), next_index + 1)),
+        Some(token) => Err(Error::InvalidToken(String::from("import"), token.to_string(), next_index)),
+        None => Err(Error::EndOfToken(String::from("import"), next_index)),
+    }
+}
+
+fn parse_rewrite(tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
+    let get_type = |index: usize| tokens.get(index).map(|x| &x.tokentype).ok_or_else(|| Error::EndOfToken(String::from("rewrite"), index));
+    let invalid = |index: usize| Error::InvalidToken(String::from("rewrite"), tokens[index].to_string(), index);
+
+    let (target, is_literal, next_index) = match get_type(index)? {
+        TokenType::Value(value) if !value.is_empty() => (RewritePattern::Literal(value.clone()), true, index + 1),
+        TokenType::Regex(pattern) => (RewritePattern::Regex(pattern.clone()), false, index + 1),
+        _ => return Err(invalid(index)),
+    };
+
+    if get_type(next_index)? != &TokenType::Arrow {
+        return Err(invalid(next_index));
+    }
+    let (replacement, mut next_index) = match get_type(next_index + 1)? {
+        // `$` only refers to groups of a regex target.
+        TokenType::Value(value) if is_literal => (value.replace('$', "$$"), next_index + 2),
+        TokenType::Value(value) => (value.clone(), next_index + 2),
+        _ => return Err(invalid(next_index + 1)),
+    };
+
+    let get_context = |index: usize| match tokens.get(index).map(|x| &x.tokentype) {
+        Some(TokenType::Value(value)) => Some((RewritePattern::Literal(value.clone()), index + 1)),
+        Some(TokenType::Regex(pattern)) => Some((RewritePattern::Regex(pattern.clone()), index + 1)),
+        _ => None,
+    };
+
+    let (mut left, mut right) = (None, None);
+    if get_type(next_index)? == &TokenType::Variable(String::from("when")) {
+        next_index += 1;
+        if let Some((pattern, index)) = get_context(next_index) {
+            left = Some(pattern);
+            next_index = index;
+        }
+        if get_type(next_index)? != &TokenType::Variable(String::from("_")) {
+            return Err(invalid(next_index));
+        }
+        next_index += 1;
+        if let Some((pattern, index)) = get_context(next_index) {
+            right = Some(pattern);
+            next_index = index;
+        }
+    }
+
+    let after_exclude = get_type(next_index)? == &TokenType::Variable(String::from("after"));
+    if after_exclude {
+        if get_type(next_index + 1)? != &TokenType::Variable(String::from("exclude")) {
+            return Err(invalid(next_index + 1));
+        }
+        next_index += 2;
+    }
+
+    if get_type(next_index)? != &TokenType::Semicolon {
+        return Err(invalid(next_index));
+    }
+
+    let rewrite = RewriteStruct::new(RewriteSource { target, left, right }, replacement, after_exclude, Some(index))?;
+
+    Ok((Statement::Rewrite(rewrite), next_index + 1))
+}
+
+fn parse_map(tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
+    let get_type = |index: usize| tokens.get(index).map(|x| &x.tokentype).ok_or_else(|| Error::EndOfToken(String::from("map"), index));
+    let invalid = |index: usize| Error::InvalidToken(String::from("map"), tokens[index].to_string(), index);
+
+    let name = match get_type(index)? {
+        TokenType::Variable(name) => name.clone(),
+        _ => return Err(invalid(index)),
+    };
+    if get_type(index + 1)? != &TokenType::LeftBrace {
+        return Err(invalid(index + 1));
+    }
+
+    let mut entries = Vec::new();
+    let mut next_index = index + 2;
+    loop {
+        match get_type(next_index)? {
+            TokenType::NewLine => next_index += 1,
+            TokenType::RightBrace => break,
+            TokenType::Value(from) if !from.is_empty() => {
+                if get_type(next_index + 1)? != &TokenType::Arrow {
+                    return Err(invalid(next_index + 1));
+                }
+                let to = match get_type(next_index + 2)? {
+                    TokenType::Value(to) => to.clone(),
+                    _ => return Err(invalid(next_index + 2)),
+                };
+                if get_type(next_index + 3)? != &TokenType::Semicolon {
+                    return Err(invalid(next_index + 3));
+                }
+
+                entries.push((from.clone(), to));
+                next_index += 4;
+            },
+            _ => return Err(invalid(next_index)),
+        }
+    }
+
+    // the semicolon after the closing brace may be omitted.
+    next_index += 1;
+    if tokens.get(next_index).map(|x| &x.tokentype) == Some(&TokenType::Semicolon) {
+        next_index += 1;
+    }
+
+    Ok((Statement::Map(MapStruct { name, entries }), next_index))
+}
+
+fn parse_syllable(directive: &str, tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
+    let role = match directive {
+        "onset" => SyllableRole::Onset,
+        "nucleus" => SyllableRole::Nucleus,
+        "coda" => SyllableRole::Coda,
+        _ => SyllableRole::Syllable,
+    };
+
+    let mut variables = Vec::new();
+    let mut next_index = index;
+    loop {
+        match tokens.get(next_index).map(|x| &x.tokentype) {
+            Some(TokenType::Variable(name)) => variables.push(name.clone()),
+            Some(TokenType::Semicolon) if !variables.is_empty() => break,
+            Some(token) => return Err(Error::InvalidToken(format!("@{}", directive), token.to_string(), next_index)),
+            None => return Err(Error::EndOfToken(format!("@{}", directive), next_index)),
+        }
+        next_index += 1;
+    }
+
+    Ok((Statement::Syllable(SyllableStruct { role, variables }), next_index + 1))
+}
+
+fn parse_segments(tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
+    let mut segments = Vec::new();
+    let mut next_index = index;
+    loop {
+        match tokens.get(next_index).map(|x| &x.tokentype) {
+            Some(TokenType::Value(segment)) if !segment.is_empty() => segments.push(segment.clone()),
+            Some(TokenType::Semicolon) if !segments.is_empty() => break,
+            Some(token) => return Err(Error::InvalidToken(String::from("@segments"), token.to_string(), next_index)),
+            None => return Err(Error::EndOfToken(String::from("@segments"), next_index)),
+        }
+        next_index += 1;
+    }
+
+    Ok((Statement::Segments(SegmentsStruct { segments }), next_index + 1))
+}
+
+// The range is `{n}`, `{m,n}`, `{m,}` or `{,n}`.
+fn parse_length(tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
+    let types: Vec<&TokenType> = tokens.iter().skip(index).take(7).map(|x| &x.tokentype).collect();
+
+    let unit = match types.first() {
+        Some(TokenType::Variable(name)) if name == "chars" => LengthUnit::Chars,
+        Some(TokenType::Variable(name)) if name == "graphemes" => LengthUnit::Graphemes,
+        Some(TokenType::Variable(name)) if name == "segments" => LengthUnit::Segments,
+        Some(TokenType::Variable(name)) if name == "syllables" => LengthUnit::Syllables,
+        Some(_) => return Err(Error::InvalidToken(String::from("@length"), tokens[index].to_string(), index)),
+        None => return Err(Error::EndOfToken(String::from("@length"), index)),
+    };
+
+    let (min, max, next_index) = match &types[1..] {
+        [TokenType::LeftBrace, TokenType::Count(count), TokenType::RightBrace, ..] => (*count, Some(*count), index + 4),
+        [TokenType::LeftBrace, TokenType::Count(min), TokenType::Comma, TokenType::Count(max), TokenType::RightBrace, ..] => (*min, Some(*max), index + 6),
+        [TokenType::LeftBrace, TokenType::Count(min), TokenType::Comma, TokenType::RightBrace, ..] => (*min, None, index + 5),
+        [TokenType::LeftBrace, TokenType::Comma, TokenType::Count(max), TokenType::RightBrace, ..] => (0.0, Some(*max), index + 5),
+        [_, ..] => return Err(Error::InvalidToken(String::from("@length"), tokens[index + 1].to_string(), index + 1)),
+        [] => return Err(Error::EndOfToken(String::from("@length"), index + 1)),
+    };
+
+    if min < 0.0 || min.fract() != 0.0 || max.is_some_and(|x| x.fract() != 0.0 || x < min) {
+        return Err(Error::ErrorMessage(format!("Invalid length: {{{},{}}}", min, max.map(|x| x.to_string()).unwrap_or_default()), Some(index)))
+    }
+
+    match tokens.get(next_index).map(|x| &x.tokentype) {
+        Some(TokenType::Semicolon) => {
+            let length = LengthStruct { unit, min: min as usize, max: max.map(|x| x as usize) };
+            Ok((Statement::Length(length), next_index + 1))
+        },
+        Some(token) => Err(Error::InvalidToken(String::from("@length"), token.to_string(), next_index)),
+        None => Err(Error::EndOfToken(String::from("@length"), next_index)),
+    }
+}
+
+// Alternatives are `position [heavy | light]` separated by `|`.
+fn parse_stress(tokens: &[Token], index: usize) -> Result<(Statement, usize), Error> {
+    let get_type = |index: usize| tokens.get(index).map(|x| &x.tokentype).ok_or_else(|| Error::EndOfToken(String::from("@stress"), index));
+    let invalid = |index: usize| Error::InvalidToken(String::from("@stress"), tokens[index].to_string(), index);
+
+    let mut alternatives = Vec::new();
+    let mut next_index = index;
+    loop {
+        let position = match get_type(next_index)? {
+            TokenType::Variable(name) if name == "initial" => StressPosition::Initial,
+            TokenType::Variable(name) if name == "second" => StressPosition::Second,
+            TokenType::Variable(name) if name == "antepenult" => StressPosition::Antepenult,
+            TokenType::Variable(name) if name == "penult" => StressPosition::Penult,
+            TokenType::Variable(name) if name == "final" => StressPosition::Final,
+            _ => return Err(invalid(next_index)),
+        };
+        next_index += 1;
+
+        let heavy = match get_type(next_index)? {
+            TokenType::Variable(name) if name == "heavy" => Some(true),
+            TokenType::Variable(name) if name == "light" => Some(false),
+            _ => None,
+        };
+        if heavy.is_some() {
+            next_index += 1;
+        }
+        alternatives.push((position, heavy));
+
+        if get_type(next_index)? != &TokenType::Or {
+            break;
+        }
+        next_index += 1;
+    }
+
+    let mark = if get_type(next_index)? == &TokenType::Variable(String::from("as")) {
+        next_index += 2;
+        match get_type(next_index - 1)? {
+            TokenType::Variable(name) if name == "ipa" => StressMark::Ipa,
+            TokenType::Variable(name) if name == "acute" => StressMark::Acute,
+            _ => return Err(invalid(next_index - 1)),
+        }
+    } else {
+        StressMark::Ipa
+    };
+
+    if get_type(next_index)? != &TokenType::Semicolon {
+        return Err(invalid(next_index));
+    }
+
+    Ok((Statement::Stress(StressStruct { alternatives, mark }), next_index + 1))
+}
+
+// `%name = `, `%name 2 = ` and `% 2 = ` before the expression of generate.
+fn parse_generate_header(tokens: &[Token], index: usize) -> (Option<String>, f64, usize) {
+    let types: Vec<&TokenType> = tokens.iter().skip(index).take(3).map(|x| &x.tokentype).collect();
+
+    match types.as_slice() {
+        [TokenType::Variable(name), TokenType::Equal, ..] => (Some(name.clone()), 1.0, index + 2),
+        [TokenType::Variable(name), TokenType::Count(weight), TokenType::Equal] => (Some(name.clone()), *weight, index + 3),
+        [TokenType::Count(weight), TokenType::Equal, ..] => (None, *weight, index + 2),
+        _ => (None, 1.0, index),
+    }
+}
+
 fn parse_expression(tokens: &[Token], index: usize) -> Result<(Expression, usize), Error> {
-    let (patterns, next_index) = parse_patterns(&tokens, index)?;
+    let (patterns, next_index) = parse_patterns(tokens, index, false)?;
 
     let (excludes, next_index) = if let Some(TokenType::Minus) = tokens.get(next_index).map(|x| &x.tokentype) {
-        parse_patterns(&tokens, next_index + 1)?
+        parse_patterns(tokens, next_index + 1, true)?
     } else {
         (Vec::new(), next_index)
     };
@@ -307,14 +943,15 @@ fn parse_expression(tokens: &[Token], index: usize) -> Result<(Expression, usize
     Ok((Expression { patterns, excludes: Exclude::Pattern(excludes) }, next_index))
 }
 
-fn parse_patterns(tokens: &[Token], index: usize) -> Result<(Vec<Pattern>, usize), Error> {
-    let (pattern, mut next_index) = parse_pattern(&tokens, index)?;
+fn parse_patterns(tokens: &[Token], index: usize, in_exclude: bool) -> Result<(Vec<Pattern>, usize), Error> {
+    let (distribution, next_index) = if in_exclude { (None, index) } else { parse_distribution(tokens, index)? };
+    let (pattern, mut next_index) = parse_pattern(tokens, next_index, in_exclude)?;
     let mut patterns = vec![pattern];
 
     loop {
         if let Some(value) = tokens.get(next_index) {
             if TokenType::Or == value.tokentype {
-                next_index = next_index + 1;
+                next_index += 1;
             } else {
                 break;
             }
@@ -322,7 +959,7 @@ fn parse_patterns(tokens: &[Token], index: usize) -> Result<(Vec<Pattern>, usize
             return Err(Error::EndOfToken(String::from("patterns"), next_index))
         };
 
-        if let Ok((pattern, index)) = parse_pattern(&tokens, next_index) {
+        if let Ok((pattern, index)) = parse_pattern(tokens, next_index, in_exclude) {
             patterns.push(pattern);
             next_index = index;
         } else {
@@ -330,10 +967,40 @@ fn parse_patterns(tokens: &[Token], index: usize) -> Result<(Vec<Pattern>, usize
         }
     }
 
+    if let Some(distribution) = distribution {
+        for (rank, pattern) in patterns.iter_mut().enumerate() {
+            pattern.count *= distribution.weight(rank + 1);
+        }
+    }
+
     Ok((patterns, next_index))
 }
 
-fn parse_pattern(tokens: &[Token], index: usize) -> Result<(Pattern, usize), Error> {
+// `@zipf(s)`, `@geometric(p)` or `@uniform` before the patterns.
+fn parse_distribution(tokens: &[Token], index: usize) -> Result<(Option<Distribution>, usize), Error> {
+    let types: Vec<&TokenType> = tokens.iter().skip(index).take(4).map(|x| &x.tokentype).collect();
+
+    let (distribution, next_index) = match types.as_slice() {
+        [TokenType::Directive(name), ..] if name == "uniform" => (Distribution::Uniform, index + 1),
+        [TokenType::Directive(name), TokenType::LeftCirc, TokenType::Count(value), TokenType::RightCirc] if name == "zipf" => {
+            (Distribution::Zipf(*value), index + 4)
+        },
+        [TokenType::Directive(name), TokenType::LeftCirc, TokenType::Count(value), TokenType::RightCirc] if name == "geometric" => {
+            if *value <= 0.0 || *value > 1.0 {
+                return Err(Error::ErrorMessage(format!("Invalid distribution: @geometric({})", value), Some(index)))
+            }
+            (Distribution::Geometric(*value), index + 4)
+        },
+        [TokenType::Directive(name), ..] if name == "zipf" || name == "geometric" => {
+            return Err(Error::InvalidToken(String::from("distribution"), tokens[index].to_string(), index))
+        },
+        _ => return Ok((None, index)),
+    };
+
+    Ok((Some(distribution), next_index))
+}
+
+fn parse_pattern(tokens: &[Token], index: usize, in_exclude: bool) -> Result<(Pattern, usize), Error> {
     let (is_prefix, next_index) = if let Some(token) = tokens.get(index) {
         match token.tokentype {
             TokenType::Circumflex => (true, index + 1),
@@ -343,7 +1010,15 @@ fn parse_pattern(tokens: &[Token], index: usize) -> Result<(Pattern, usize), Err
         return Err(Error::EndOfToken(String::from("pattern (prefix)"), index))
     };
     
-    let (values, next_index) = parse_values(&tokens, next_index)?;
+    let (values, next_index) = parse_values(tokens, next_index, in_exclude)?;
+    for (position, value) in values.iter().enumerate() {
+        if let Value::Backreference(reference) = value {
+            if *reference >= position {
+                return Err(Error::ErrorMessage(format!("Backreference must refer to a previous value: &{}", reference + 1), Some(index)))
+            }
+        }
+    }
+
     let (count, next_index) = match tokens.get(next_index) {
         Some(value) => {
             if let TokenType::Count(value) = value.tokentype {
@@ -376,28 +1051,50 @@ fn parse_pattern(tokens: &[Token], index: usize) -> Result<(Pattern, usize), Err
     Ok((Pattern::new(values, count, mode), next_index))
 }
 
-fn parse_values(tokens: &[Token], index: usize) -> Result<(Vec<Value>, usize), Error> {
-    let (value, mut next_index) = parse_value(&tokens, index)?;
+fn parse_values(tokens: &[Token], index: usize, in_exclude: bool) -> Result<(Vec<Value>, usize), Error> {
+    let (value, next_index) = parse_value(tokens, index, in_exclude)?;
+    let (value, mut next_index) = parse_quantifier(tokens, next_index, value)?;
     let mut values = vec![value];
 
-    loop {
-        if let Ok((value, index)) = parse_value(&tokens, next_index) {
-            values.push(value);
-            next_index = index;
-        } else {
-            break;
-        }
+    while let Ok((value, index)) = parse_value(tokens, next_index, in_exclude) {
+        let (value, index) = parse_quantifier(tokens, index, value)?;
+        values.push(value);
+        next_index = index;
     }
 
     Ok((values, next_index))
 }
 
-fn parse_value(tokens: &[Token], index: usize) -> Result<(Value, usize), Error> {
+// `?`, `{n}` or `{m,n}` after a value.
+fn parse_quantifier(tokens: &[Token], index: usize, value: Value) -> Result<(Value, usize), Error> {
+    let types: Vec<&TokenType> = tokens.iter().skip(index).take(5).map(|x| &x.tokentype).collect();
+
+    let (min, max, next_index) = match types.as_slice() {
+        [TokenType::Question, ..] => (0.0, 1.0, index + 1),
+        [TokenType::LeftBrace, TokenType::Count(count), TokenType::RightBrace, ..] => (*count, *count, index + 3),
+        [TokenType::LeftBrace, TokenType::Count(min), TokenType::Comma, TokenType::Count(max), TokenType::RightBrace] => (*min, *max, index + 5),
+        [TokenType::LeftBrace, ..] => return Err(Error::InvalidToken(String::from("quantifier"), tokens[index].to_string(), index)),
+        _ => return Ok((value, index)),
+    };
+
+    if matches!(value, Value::Backreference(_)) {
+        return Err(Error::ErrorMessage(String::from("Quantifier can not be used with backreference"), Some(index)))
+    }
+    if min < 0.0 || min.fract() != 0.0 || max.fract() != 0.0 || max < min {
+        return Err(Error::ErrorMessage(format!("Invalid quantifier: {{{},{}}}", min, max), Some(index)))
+    }
+
+    Ok((Value::Repeat(Box::new(value), min as usize, max as usize), next_index))
+}
+
+fn parse_value(tokens: &[Token], index: usize, in_exclude: bool) -> Result<(Value, usize), Error> {
     if let Some(token) = tokens.get(index) {
         match &token.tokentype {
             TokenType::Value(value) => Ok((Value::Literal(value.to_owned()), index + 1)),
             TokenType::Variable(value) => Ok((Value::Variable(value.to_owned()), index + 1)),
-            TokenType::LeftCirc => parse_inner_patterns(&tokens, index + 1),
+            TokenType::LeftCirc => parse_inner_patterns(tokens, index + 1, in_exclude),
+            TokenType::Ampersand(reference) => Ok((Value::Backreference(*reference as usize), index + 1)),
+            TokenType::Regex(pattern) if in_exclude => Ok((Value::Regex(pattern.to_owned()), index + 1)),
             _ => Err(Error::InvalidToken(String::from("value"), token.to_string(), index)),
         }
     } else {
@@ -405,8 +1102,8 @@ fn parse_value(tokens: &[Token], index: usize) -> Result<(Value, usize), Error>
     }
 }
 
-fn parse_inner_patterns(tokens: &[Token], index: usize) -> Result<(Value, usize), Error> {
-    let (patterns, next_index) = parse_patterns(&tokens, index)?;
+fn parse_inner_patterns(tokens: &[Token], index: usize, in_exclude: bool) -> Result<(Value, usize), Error> {
+    let (patterns, next_index) = parse_patterns(tokens, index, in_exclude)?;
 
     if let Some(token) = tokens.get(next_index) {
         if TokenType::RightCirc == token.tokentype {
@@ -423,11 +1120,11 @@ fn parse_inner_patterns(tokens: &[Token], index: usize) -> Result<(Value, usize)
 mod parse_test {
     use crate::lexer::TokenType;
 
-    use super::{Statement, Error};
+    use super::{Statement, SyllableRole, StressPosition, StressMark, LengthUnit, Error};
 
     fn execute(s: &str) -> Result<Vec<Statement>, Error> {
         let tokens = crate::lexer::lexer(s);
-        crate::parser::parse(&tokens)
+        crate::parser::parse(&tokens).and_then(crate::parser::convert_statement_exclude)
     }
     
     #[test]
@@ -568,6 +1265,253 @@ mod parse_test {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn named_generate() {
+        let result = execute(r#"
+        C = "p" | "t" | "k";
+        V = "a" | "i";
+
+        %noun = C V;
+        %verb 2 = C V C - "i" ^;
+        % 3 = V;
+        % C V;
+        "#).unwrap();
+
+        let generates: Vec<(Option<&str>, f64)> = result.iter().filter_map(|x| match x {
+            Statement::Generate(generate) => Some((generate.name.as_deref(), generate.weight)),
+            _ => None,
+        }).collect();
+        assert_eq!(generates, vec![(Some("noun"), 1.0), (Some("verb"), 2.0), (None, 3.0), (None, 1.0)]);
+    }
+
+    #[test]
+    fn import() {
+        let tokens = crate::lexer::lexer(r#"
+        @import "common.zatlin";
+        @import "../shared/vowels.zatlin" as Vowels;
+        % C Vowels.V;
+        "#);
+        let result = crate::parser::parse(&tokens).unwrap();
+
+        let imports: Vec<(&str, Option<&str>)> = result.iter().filter_map(|x| match x {
+            Statement::Import(import) => Some((import.path.as_str(), import.namespace.as_deref())),
+            _ => None,
+        }).collect();
+        assert_eq!(imports, vec![("common.zatlin", None), ("../shared/vowels.zatlin", Some("Vowels"))]);
+
+        let tokens = crate::lexer::lexer(r#"@import "common.zatlin" as;"#);
+        assert!(matches!(crate::parser::parse(&tokens), Err(Error::InvalidToken(_, _, _))));
+    }
+
+    #[test]
+    fn quantifier() {
+        let result = execute(r#"
+        C = "p" | "t" | "k";
+        V = "a" | "i";
+
+        % C? V (C V){1,3} "n"{2} - V{2};
+        "#).unwrap();
+
+        let Some(Statement::Generate(generate)) = result.last() else { panic!() };
+        let values: Vec<String> = generate.expr.patterns[0].values.iter().map(|x| match x {
+            super::Value::Repeat(_, min, max) => format!("{{{},{}}}", min, max),
+            _ => String::from("value"),
+        }).collect();
+        assert_eq!(values, vec!["{0,1}", "value", "{1,3}", "{2,2}"]);
+
+        let super::Exclude::Regex(regex) = &generate.expr.excludes else { panic!() };
+        assert!(regex.is_match("pai") && !regex.is_match("pap"));
+
+        assert!(execute(r#"% "a"{3,1};"#).is_err());
+        assert!(execute(r#"% "a"{1,};"#).is_err());
+        assert!(execute(r#"% "a" &1?;"#).is_err());
+    }
+
+    #[test]
+    fn regex() {
+        let result = execute(r#"
+        V = "a" | "e" | "i";
+
+        % V V V V - /[ae]{3}/ | ^ "i" /i+/ ^;
+        "#).unwrap();
+
+        let Some(Statement::Generate(generate)) = result.last() else { panic!() };
+        let super::Exclude::Regex(regex) = &generate.expr.excludes else { panic!() };
+        assert!(regex.is_match("iaea") && regex.is_match("iii") && !regex.is_match("iaie"));
+
+        let result = execute(r#"
+        V = "a" | "e" | "i";
+        % V V - /[ae/;
+        "#);
+        assert_eq!(result.unwrap_err(), Error::InvalidRegex(String::from("[ae"), 3, 16));
+
+        // regex can not generate words.
+        assert!(execute(r#"% /[ae]/;"#).is_err());
+    }
+
+    #[test]
+    fn rewrite() {
+        let result = execute(r#"
+        rewrite "np" -> "mp";
+        rewrite /([aiu])h/ -> "$1$1" when "t" _ after exclude;
+        rewrite "n" -> "ŋ" when _ /[kg]/;
+        rewrite = "a";
+        % rewrite;
+        "#).unwrap();
+
+        let rules: Vec<(&str, bool, bool, bool)> = result.iter().filter_map(|x| match x {
+            Statement::Rewrite(rule) => Some((rule.target.as_str(), rule.left.is_some(), rule.right.is_some(), rule.after_exclude)),
+            _ => None,
+        }).collect();
+        assert_eq!(rules, vec![("^(?:np)", false, false, false), ("^(?:([aiu])h)", true, false, true), ("^(?:n)", false, true, false)]);
+
+        assert!(execute(r#"rewrite "np" "mp";"#).is_err());
+        assert!(execute(r#"rewrite "n" -> "m" when "a";"#).is_err());
+        assert!(execute(r#"rewrite "n" -> "m" after;"#).is_err());
+    }
+
+    #[test]
+    fn map() {
+        let result = execute(r#"
+        map latin {
+            "ʃ" -> "sh";
+            "a" -> "a";
+        }
+        map kana { "ʃa" -> "しゃ"; };
+        map = "a";
+        % map;
+        "#).unwrap();
+
+        let maps: Vec<(&str, Vec<(String, String)>)> = result.iter().filter_map(|x| match x {
+            Statement::Map(map) => Some((map.name.as_str(), map.entries.clone())),
+            _ => None,
+        }).collect();
+        assert_eq!(maps, vec![
+            ("latin", vec![(String::from("ʃ"), String::from("sh")), (String::from("a"), String::from("a"))]),
+            ("kana", vec![(String::from("ʃa"), String::from("しゃ"))]),
+        ]);
+
+        assert!(execute(r#"map latin { "ʃ" -> "sh" }"#).is_err());
+        assert!(execute(r#"map latin { "" -> "sh"; }"#).is_err());
+        assert!(execute(r#"map latin { "ʃ" -> "sh";"#).is_err());
+    }
+
+    #[test]
+    fn syllable() {
+        let result = execute(r#"
+        @syllable S;
+        @onset C Cx;
+        @nucleus V;
+        S = C V | Cx V;
+        C = "p"; Cx = "pr"; V = "a";
+        % S S;
+        "#).unwrap();
+
+        let roles: Vec<(SyllableRole, Vec<String>)> = result.iter().filter_map(|x| match x {
+            Statement::Syllable(syllable) => Some((syllable.role, syllable.variables.clone())),
+            _ => None,
+        }).collect();
+        assert_eq!(roles, vec![
+            (SyllableRole::Syllable, vec![String::from("S")]),
+            (SyllableRole::Onset, vec![String::from("C"), String::from("Cx")]),
+            (SyllableRole::Nucleus, vec![String::from("V")]),
+        ]);
+
+        assert!(execute(r#"@syllable; S = "a"; % S;"#).is_err());
+        assert!(execute(r#"@syllable "S"; S = "a"; % S;"#).is_err());
+        assert_eq!(execute(r#"@coda Ce; S = "a"; % S;"#).err(), Some(Error::NotFoundVariable(String::from("Ce"))));
+    }
+
+    #[test]
+    fn stress() {
+        let result = execute(r#"
+        @stress final heavy | penult;
+        @stress initial as acute;
+        % "a";
+        "#).unwrap();
+
+        let stresses: Vec<_> = result.iter().filter_map(|x| match x {
+            Statement::Stress(stress) => Some((stress.alternatives.clone(), stress.mark)),
+            _ => None,
+        }).collect();
+        assert_eq!(stresses, vec![
+            (vec![(StressPosition::Final, Some(true)), (StressPosition::Penult, None)], StressMark::Ipa),
+            (vec![(StressPosition::Initial, None)], StressMark::Acute),
+        ]);
+
+        assert!(execute(r#"@stress; % "a";"#).is_err());
+        assert!(execute(r#"@stress last; % "a";"#).is_err());
+        assert!(execute(r#"@stress final |; % "a";"#).is_err());
+        assert!(execute(r#"@stress final as grave; % "a";"#).is_err());
+    }
+
+    #[test]
+    fn distribution() {
+        let result = execute(r#"
+        C = @zipf(1) "p" | "t" 3 | "k" | "s";
+        V = @geometric(0.5) "a" | "i" | (@uniform "u" | "o" | "e" 2);
+        % C V;
+        "#).unwrap();
+
+        let counts: Vec<Vec<f64>> = result.iter().filter_map(|x| match x {
+            Statement::Define(define) => Some(define.expr.patterns.iter().map(|x| x.count).collect()),
+            _ => None,
+        }).collect();
+        assert_eq!(counts, vec![vec![1.0, 1.5, 1.0 / 3.0, 0.25], vec![0.5, 0.25, 0.125]]);
+
+        let Some(Statement::Define(define)) = result.get(1) else { panic!() };
+        let super::Value::InnerPattern(patterns) = &define.expr.patterns[2].values[0] else { panic!() };
+        assert_eq!(patterns.iter().map(|x| x.count).collect::<Vec<f64>>(), vec![1.0, 1.0, 2.0]);
+
+        assert!(execute(r#"% @geometric(1.5) "a" | "b";"#).is_err());
+        assert!(execute(r#"% @zipf "a" | "b";"#).is_err());
+        assert!(execute(r#"% "a" - @uniform "b";"#).is_err());
+    }
+
+    #[test]
+    fn length() {
+        let result = execute(r#"
+        @length syllables {2,4};
+        @length chars {,8};
+        @length graphemes {3,};
+        @length chars {5};
+        @length segments {1,2};
+        % "a";
+        "#).unwrap();
+
+        let lengths: Vec<_> = result.iter().filter_map(|x| match x {
+            Statement::Length(length) => Some((length.unit, length.min, length.max)),
+            _ => None,
+        }).collect();
+        assert_eq!(lengths, vec![
+            (LengthUnit::Syllables, 2, Some(4)),
+            (LengthUnit::Chars, 0, Some(8)),
+            (LengthUnit::Graphemes, 3, None),
+            (LengthUnit::Chars, 5, Some(5)),
+            (LengthUnit::Segments, 1, Some(2)),
+        ]);
+
+        assert!(execute(r#"@length words {2}; % "a";"#).is_err());
+        assert!(execute(r#"@length chars {4,2}; % "a";"#).is_err());
+        assert!(execute(r#"@length chars {1.5}; % "a";"#).is_err());
+        assert!(execute(r#"@length chars 2; % "a";"#).is_err());
+    }
+
+    #[test]
+    fn segments() {
+        let result = execute(r#"
+        @segments "sh" "ts";
+        % "a";
+        "#).unwrap();
+
+        let Some(Statement::Segments(segments)) = result.first() else { panic!() };
+        assert_eq!(segments.segments, vec![String::from("sh"), String::from("ts")]);
+
+        assert!(execute(r#"@segments; % "a";"#).is_err());
+        assert!(execute(r#"@segments ""; % "a";"#).is_err());
+        assert!(execute(r#"@segments sh; % "a";"#).is_err());
+    }
+
     #[test]
     fn nothing_semicolon() {
         let result = execute(r#"
@@ -605,4 +1549,34 @@ mod parse_test {
             Ok(_) => false,
         })
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn backreference() {
+        let result = execute(r#"
+        C = "p" | "t" | "k";
+        V = "a" | "i" | "u";
+
+        % C V C | C V &1 &2 - C V &1;
+        "#);
+
+        println!("{:?}", result);
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    fn invalid_backreference() {
+        let result = execute(r#"
+        C = "p" | "t" | "k";
+        V = "a" | "i" | "u";
+
+        % C V &3;
+        "#);
+
+        println!("{:?}", result);
+        assert!(match result {
+            Err(Error::ErrorMessage(message, _)) => message.starts_with("Backreference must refer to a previous value"),
+            Err(_) => false,
+            Ok(_) => false,
+        })
+    }
+}
@@ -0,0 +1,48 @@
+use crate::parser::RewriteStruct;
+
+impl RewriteStruct {
+    // Every match is replaced at once, from left to right without overlapping,
+    // and the contexts are checked against the word before this rule.
+    pub(crate) fn apply(&self, word: &str) -> String {
+        let mut result = String::default();
+        let mut position = 0;
+        let mut last_end: Option<usize> = None;
+
+        loop {
+            let matched = self.target.captures(&word[position..]).and_then(|captures| {
+                let end = position + captures.get(0)?.end();
+                let left = self.left.as_ref().is_none_or(|x| x.is_match(&word[..position]));
+                let right = self.right.as_ref().is_none_or(|x| x.is_match(&word[end..]));
+
+                // an empty match just after another match is skipped, like `Regex::replace_all`.
+                (left && right && !(end == position && last_end == Some(position))).then_some((captures, end))
+            });
+
+            if let Some((captures, end)) = matched {
+                captures.expand(&self.replacement, &mut result);
+                last_end = Some(end);
+                if end > position {
+                    position = end;
+                    continue;
+                }
+            }
+
+            match word[position..].chars().next() {
+                Some(c) => {
+                    result.push(c);
+                    position += c.len_utf8();
+                },
+                None => break,
+            }
+        }
+
+        result
+    }
+}
+
+// Apply the rules which run before (or after) the excludes of generate, in order.
+pub(crate) fn rewrite(rules: &[&RewriteStruct], word: String, after_exclude: bool) -> String {
+    rules.iter()
+        .filter(|x| x.after_exclude == after_exclude)
+        .fold(word, |word, rule| rule.apply(&word))
+}
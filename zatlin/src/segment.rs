@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use crate::derivation::Derivation;
+use crate::error::Error;
+use crate::parser::{DefineStruct, Exclude, Expression, GenerateStruct, Pattern, RewritePattern, RewriteSource, RewriteStruct, SegmentsStruct, Statement, Value};
+
+// Each declared segment is written as one private use character from U+F0000,
+// so that excludes, backreferences and lengths treat it as one unit.
+const FIRST_SEGMENT: u32 = 0xF0000;
+const MAX_SEGMENTS: usize = 0xFFFE;
+
+impl SegmentsStruct {
+    pub(crate) fn encode(&self, text: &str) -> String {
+        let mut result = String::default();
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            let (c, length) = self.find(rest).unwrap_or((c, c.len_utf8()));
+            result.push(c);
+            rest = &rest[length..];
+        }
+
+        result
+    }
+
+    // Escapes and character classes of the regex are kept as they are.
+    fn encode_regex(&self, pattern: &str) -> String {
+        let mut result = String::default();
+        let mut rest = pattern;
+        let mut depth = 0;
+
+        while let Some(c) = rest.chars().next() {
+            let (c, length) = match c {
+                '\\' => {
+                    let length = 1 + rest[1..].chars().next().map_or(0, |x| x.len_utf8());
+                    result.push_str(&rest[..length]);
+                    rest = &rest[length..];
+                    continue;
+                },
+                '[' => { depth += 1; (c, 1) },
+                ']' if depth > 0 => { depth -= 1; (c, 1) },
+                _ if depth > 0 => (c, c.len_utf8()),
+                _ => self.find(rest).unwrap_or((c, c.len_utf8())),
+            };
+            result.push(c);
+            rest = &rest[length..];
+        }
+
+        result
+    }
+
+    // The character of the longest segment at the start of `text`, and the length it replaces.
+    fn find(&self, text: &str) -> Option<(char, usize)> {
+        self.segments.iter().enumerate()
+            .filter(|(_, x)| text.starts_with(x.as_str()))
+            .fold(None, |longest: Option<(usize, &String)>, x| match longest {
+                Some(longest) if longest.1.len() >= x.1.len() => Some(longest),
+                _ => Some(x),
+            })
+            .and_then(|(index, segment)| Some((char::from_u32(FIRST_SEGMENT + index as u32)?, segment.len())))
+    }
+
+    pub(crate) fn decode(&self, text: &str) -> String {
+        let mut result = String::default();
+        for c in text.chars() {
+            match (c as u32).checked_sub(FIRST_SEGMENT).and_then(|x| self.segments.get(x as usize)) {
+                Some(segment) => result.push_str(segment),
+                None => result.push(c),
+            }
+        }
+
+        result
+    }
+
+    // Offsets of the children are moved to the decoded text of their parent.
+    pub(crate) fn decode_derivation(&self, derivation: &Derivation) -> Derivation {
+        let children = derivation.children.iter()
+            .map(|x| self.decode_derivation(x).at(self.decode(&derivation.text[..x.offset]).len()))
+            .collect();
+
+        Derivation {
+            kind: derivation.kind.clone(),
+            pattern: derivation.pattern,
+            text: self.decode(&derivation.text),
+            offset: derivation.offset,
+            children,
+        }
+    }
+
+    fn encode_expression(&self, expression: &Expression) -> Expression {
+        let excludes = match &expression.excludes {
+            Exclude::Pattern(patterns) => Exclude::Pattern(self.encode_patterns(patterns)),
+            Exclude::Regex(regex) => Exclude::Regex(regex.clone()),
+        };
+
+        Expression { patterns: self.encode_patterns(&expression.patterns), excludes }
+    }
+
+    fn encode_patterns(&self, patterns: &[Pattern]) -> Vec<Pattern> {
+        patterns.iter().map(|pattern| {
+            let values = pattern.values.iter().map(|value| self.encode_value(value)).collect();
+
+            Pattern { values, ..pattern.clone() }
+        }).collect()
+    }
+
+    fn encode_value(&self, value: &Value) -> Value {
+        match value {
+            Value::Literal(text) => Value::Literal(self.encode(text)),
+            Value::Regex(pattern) => Value::Regex(self.encode_regex(pattern)),
+            Value::InnerPattern(patterns) => Value::InnerPattern(self.encode_patterns(patterns)),
+            Value::Repeat(value, min, max) => Value::Repeat(Box::new(self.encode_value(value)), *min, *max),
+            value => value.clone(),
+        }
+    }
+
+    fn encode_rewrite(&self, rule: &RewriteStruct) -> Result<RewriteStruct, Error> {
+        let encode = |pattern: &RewritePattern| match pattern {
+            RewritePattern::Literal(text) => RewritePattern::Literal(self.encode(text)),
+            RewritePattern::Regex(pattern) => RewritePattern::Regex(self.encode_regex(pattern)),
+        };
+        let source = RewriteSource {
+            target: encode(&rule.source.target),
+            left: rule.source.left.as_ref().map(encode),
+            right: rule.source.right.as_ref().map(encode),
+        };
+
+        RewriteStruct::new(source, self.encode(&rule.replacement), rule.after_exclude, None)
+    }
+}
+
+// Run after `@import` statements are resolved. Every `@segments` statement is merged into the first one.
+pub(crate) fn encode_segments(statements: Vec<Statement>) -> Result<Vec<Statement>, Error> {
+    let segments = SegmentsStruct {
+        segments: statements.iter().filter_map(|x| match x {
+            Statement::Segments(segments) => Some(segments.segments.iter().cloned()),
+            _ => None,
+        }).flatten().collect(),
+    };
+
+    if segments.segments.is_empty() {
+        return Ok(statements);
+    }
+    if segments.segments.len() > MAX_SEGMENTS {
+        return Err(Error::ErrorMessage(format!("Too many segments: {}", segments.segments.len()), None));
+    }
+
+    let mut result = vec![Statement::Segments(segments.clone())];
+    for statement in statements.into_iter() {
+        result.push(match statement {
+            Statement::Define(define) => Statement::Define(DefineStruct {
+                expr: Arc::new(segments.encode_expression(&define.expr)),
+                ..define
+            }),
+            Statement::Generate(generate) => Statement::Generate(GenerateStruct {
+                expr: Arc::new(segments.encode_expression(&generate.expr)),
+                ..generate
+            }),
+            Statement::Rewrite(rule) => Statement::Rewrite(segments.encode_rewrite(&rule)?),
+            Statement::Segments(_) => continue,
+            statement => statement,
+        });
+    }
+
+    Ok(result)
+}
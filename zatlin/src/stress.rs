@@ -0,0 +1,64 @@
+use crate::parser::{StressMark, StressPosition, StressStruct};
+use crate::syllable::{LocatedSyllable, Syllable};
+
+impl StressPosition {
+    fn index(&self, length: usize) -> Option<usize> {
+        match self {
+            Self::Initial => (length > 0).then_some(0),
+            Self::Second => (length > 1).then_some(1),
+            Self::Antepenult => length.checked_sub(3),
+            Self::Penult => length.checked_sub(2),
+            Self::Final => length.checked_sub(1),
+        }
+    }
+}
+
+impl StressMark {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ipa => "\u{2C8}",
+            Self::Acute => "\u{301}",
+        }
+    }
+}
+
+impl StressStruct {
+    // The first alternative which fits the word. When none fits, the syllable
+    // nearest to the position of the last alternative is stressed.
+    pub(crate) fn choose(&self, syllables: &[Syllable]) -> Option<usize> {
+        let length = syllables.len();
+        if length == 0 {
+            return None;
+        }
+
+        self.alternatives.iter()
+            .find_map(|(position, heavy)| {
+                position.index(length).filter(|x| heavy.is_none_or(|heavy| syllables[*x].is_heavy() == heavy))
+            })
+            .or_else(|| match self.alternatives.last()?.0 {
+                position @ (StressPosition::Initial | StressPosition::Second) => Some(position.index(length).unwrap_or(length - 1)),
+                position => Some(position.index(length).unwrap_or(0)),
+            })
+    }
+
+    // `word` is the text of the derivation which `syllables` are located in.
+    pub(crate) fn mark(&self, word: &str, syllables: &[LocatedSyllable]) -> String {
+        let plain: Vec<Syllable> = syllables.iter().map(|x| x.syllable.clone()).collect();
+        let stressed = match self.choose(&plain) {
+            Some(index) => &syllables[index],
+            None => return word.to_owned(),
+        };
+
+        let position = match self.mark {
+            StressMark::Ipa => stressed.offset,
+            StressMark::Acute => {
+                let offset = stressed.nucleus.unwrap_or(stressed.offset);
+                offset + word[offset..].chars().next().map_or(0, |x| x.len_utf8())
+            },
+        };
+
+        let mut result = word.to_owned();
+        result.insert_str(position, self.mark.as_str());
+        result
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use crate::derivation::{Derivation, DerivationKind};
+use crate::parser::{Statement, SyllableRole};
+
+/// A generated word split into the syllables marked by `@syllable`.
+/// `stress` is the index of the syllable stressed by `@stress`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    pub syllables: Vec<Syllable>,
+    pub stress: Option<usize>,
+}
+
+/// Text of one syllable and of the parts marked by `@onset`, `@nucleus` and `@coda` in it.
+/// A part is empty when no variable of the role produced text.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Syllable {
+    pub text: String,
+    pub onset: String,
+    pub nucleus: String,
+    pub coda: String,
+}
+
+impl Syllable {
+    /// A syllable is heavy when it has a coda or a nucleus of more than one character.
+    /// `@stress` counts a declared segment as one character.
+    pub fn is_heavy(&self) -> bool {
+        !self.coda.is_empty() || self.nucleus.chars().count() > 1
+    }
+}
+
+// A syllable with the byte offsets of itself and of its nucleus in the word.
+pub(crate) struct LocatedSyllable {
+    pub offset: usize,
+    pub nucleus: Option<usize>,
+    pub syllable: Syllable,
+}
+
+pub(crate) fn get_roles(statements: &[Statement]) -> HashMap<&str, SyllableRole> {
+    let mut roles = HashMap::new();
+    for statement in statements.iter() {
+        if let Statement::Syllable(syllable) = statement {
+            roles.extend(syllable.variables.iter().map(|x| (x.as_str(), syllable.role)));
+        }
+    }
+
+    roles
+}
+
+pub(crate) fn syllabify(derivation: &Derivation, roles: &HashMap<&str, SyllableRole>) -> Vec<Syllable> {
+    locate(derivation, roles, 0).into_iter().map(|x| x.syllable).collect()
+}
+
+// The outermost nodes of a syllable variable are syllables, and text outside of them belongs to no syllable.
+pub(crate) fn locate(derivation: &Derivation, roles: &HashMap<&str, SyllableRole>, base: usize) -> Vec<LocatedSyllable> {
+    let offset = base + derivation.offset;
+
+    match get_role(derivation, roles) {
+        Some(SyllableRole::Syllable) => {
+            let mut located = LocatedSyllable {
+                offset,
+                nucleus: None,
+                syllable: Syllable { text: derivation.text.clone(), ..Default::default() },
+            };
+            derivation.children.iter().for_each(|x| fill_parts(x, roles, offset, &mut located));
+            vec![located]
+        },
+        _ => derivation.children.iter().flat_map(|x| locate(x, roles, offset)).collect(),
+    }
+}
+
+fn fill_parts(derivation: &Derivation, roles: &HashMap<&str, SyllableRole>, base: usize, located: &mut LocatedSyllable) {
+    let offset = base + derivation.offset;
+    let syllable = &mut located.syllable;
+
+    match get_role(derivation, roles) {
+        Some(SyllableRole::Onset) => syllable.onset.push_str(&derivation.text),
+        Some(SyllableRole::Nucleus) => {
+            syllable.nucleus.push_str(&derivation.text);
+            located.nucleus.get_or_insert(offset);
+        },
+        Some(SyllableRole::Coda) => syllable.coda.push_str(&derivation.text),
+        _ => derivation.children.iter().for_each(|x| fill_parts(x, roles, offset, located)),
+    }
+}
+
+fn get_role(derivation: &Derivation, roles: &HashMap<&str, SyllableRole>) -> Option<SyllableRole> {
+    match &derivation.kind {
+        DerivationKind::Variable(name) => roles.get(name.as_str()).copied(),
+        _ => None,
+    }
+}
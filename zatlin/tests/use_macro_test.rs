@@ -140,4 +140,37 @@ mod macro_test {
         println!("");
         assert!(result.iter().all(|x| x.is_ok()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn macro_named_generate() {
+        let data: Data = zatlin!{
+            C = "p" | "t" | "k";
+            V = "a" | "i";
+
+            %noun = C V "n";
+            %verb 2 = C V C V - ^ "t";
+            % 3 = V;
+        }.unwrap();
+
+        assert_eq!(data.generate_names(), vec!["noun", "verb"]);
+
+        let generator = Zatlin::default();
+        assert!(generator.generate_named(&data, "noun").unwrap().ends_with('n'));
+        assert!(generator.generate_named(&data, "verb").is_ok());
+        assert!(generator.generate_by(&data).is_ok());
+    }
+
+    #[test]
+    fn macro_quantifier() {
+        let data: Data = zatlin!{
+            C = "p" | "t" | "k";
+            V = "a" | "i";
+
+            % (C V){1, 3} "n"? V{2} - "aa";
+        }.unwrap();
+
+        assert!(data.accepts("pakinia"));
+        assert!(!data.accepts("pakinaa"));
+        assert!(Zatlin::default().generate_by(&data).is_ok());
+    }
+}
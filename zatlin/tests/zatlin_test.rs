@@ -1,5 +1,6 @@
+#![allow(clippy::println_empty_string, clippy::match_like_matches_macro)]
 
-use zatlin::{Zatlin, Error};
+use zatlin::{Zatlin, Error, BigUint, DerivationKind, SamplingMode, Lexicon, Syllable, Data, Normalization};
 
 fn execute(s: &str) -> Vec<Result<String, Error>> {
     let zatlin = Zatlin::default();
@@ -213,3 +214,1036 @@ fn over_retry_count() {
     assert!(result.iter().all(|x| x.is_err()));
     assert!(result.iter().all(|x| if let Err(Error::OverRetryCount) = x { true } else { false }))
 }
+
+#[test]
+fn same_seed() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "f" | "t" | "s" | "k" | "h";
+    V = "a" | "i" | "u";
+
+    % C V | C V C | V C | V C V - "h" "u" | "a" "h" ^ | "i" "h" ^ | "u" "h" ^;
+    "#).unwrap();
+
+    let first = Zatlin::with_seed(42).generate_many_by(&data, 32);
+    let second = Zatlin::with_seed(42).generate_many_by(&data, 32);
+
+    assert!(first.iter().all(|x| x.is_ok()));
+    assert_eq!(first, second);
+}
+
+#[test]
+fn share_between_threads() {
+    static ZATLIN: std::sync::OnceLock<Zatlin> = std::sync::OnceLock::new();
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i" | "u";
+
+    % C V C;
+    "#).unwrap();
+    let zatlin = ZATLIN.get_or_init(Zatlin::default);
+
+    let result: Vec<Result<String, Error>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4).map(|_| scope.spawn(|| zatlin.generate_by(&data))).collect();
+        handles.into_iter().map(|x| x.join().unwrap()).collect()
+    });
+
+    assert!(result.iter().all(|x| x.as_ref().is_ok_and(|x| x.chars().count() == 3)));
+}
+
+#[test]
+fn backreference() {
+    let result = execute(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i" | "u";
+
+    % C V &1 &2;
+    "#);
+
+    assert!(result.iter().all(|x| x.is_ok()));
+    assert!(result.iter().all(|x| {
+        let value = x.as_ref().unwrap();
+        value[0..2] == value[2..4]
+    }));
+}
+
+#[test]
+fn backreference_in_exclude() {
+    let result = execute(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i" | "u";
+
+    # no identical consonant on both sides of a vowel.
+    % C V C | C V C V C - C V &1;
+    "#);
+
+    assert!(result.iter().all(|x| x.is_ok()));
+    assert!(result.iter().all(|x| {
+        let value: Vec<char> = x.as_ref().unwrap().chars().collect();
+        value.windows(3).step_by(2).all(|x| x[0] != x[2])
+    }));
+}
+
+#[test]
+fn enumerate() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t";
+    V = "a" | "i";
+
+    # "pa" is generated by both patterns.
+    % C V | "pa" | C V C - ^ "t" | "ip" ^;
+    "#).unwrap();
+
+    let result: Vec<String> = data.enumerate().unwrap().collect();
+    assert_eq!(result, vec!["pa", "pap", "pat", "pi", "pit"]);
+}
+
+#[test]
+fn enumerate_with_backreference() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t";
+    V = "a" | "i";
+
+    % C V &1 - C "i" &1;
+    "#).unwrap();
+
+    let result: Vec<String> = data.enumerate().unwrap().collect();
+    assert_eq!(result, vec!["pap", "tat"]);
+}
+
+#[test]
+fn enumerate_recursive() {
+    let data = Zatlin::create_data(r#"
+    S = "a" | "a" S;
+
+    % S;
+    "#).unwrap();
+
+    assert!(data.enumerate().is_err());
+}
+
+#[test]
+fn count_words() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t";
+    V = "a" | "i";
+
+    # "pa" is generated by both patterns.
+    % C V | "pa" | C V C - ^ "t" | "ip" ^;
+    "#).unwrap();
+
+    assert_eq!(data.count_words().unwrap(), BigUint::from(5u32));
+}
+
+#[test]
+fn count_words_large() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k" | "s" | "m" | "n" | "l" | "r";
+    V = "a" | "e" | "i" | "o" | "u";
+    S = C V;
+
+    % S S S S S S S S S S S S S S S S S S S S S S S S S S S S S S;
+    "#).unwrap();
+
+    assert_eq!(data.count_words().unwrap(), BigUint::from(40u32).pow(30));
+}
+
+#[test]
+fn probability() {
+    let data = Zatlin::create_data(r#"
+    C = "k" 3 | "s";
+    V = "a" | "u";
+
+    # "ka" is generated by both patterns.
+    % C V 3 | "ka" - "su" ^;
+    "#).unwrap();
+
+    // Before excluding: ka = 3/4 * 3/4 * 1/2 + 1/4, su = 3/4 * 1/4 * 1/2.
+    let total = 1.0 - 3.0 / 32.0;
+    let expected = [("ka", (9.0 / 32.0 + 1.0 / 4.0) / total), ("ku", 9.0 / 32.0 / total), ("sa", 3.0 / 32.0 / total), ("su", 0.0), ("kas", 0.0)];
+
+    for (word, probability) in expected.iter() {
+        let result = data.probability(word).unwrap();
+        assert!((result - probability).abs() < 1e-9, "{}: {} != {}", word, result, probability);
+    }
+}
+
+#[test]
+fn accepts() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i" | "u";
+    N = "n" | "m" - "m";
+    S = C V | C V N;
+
+    % S | S S - C V &1;
+    "#).unwrap();
+
+    assert!(data.accepts("pa"));
+    assert!(data.accepts("tanki"));
+    assert!(!data.accepts("tam"));
+    assert!(!data.accepts("pap"));
+    assert!(!data.accepts("pan pa"));
+    assert!(!data.accepts(""));
+}
+
+#[test]
+fn derive() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i" | "u";
+
+    % C V | C V ("n" | "m");
+    "#).unwrap();
+
+    let derivation = data.derive("kum").unwrap().unwrap();
+    assert_eq!(derivation.kind, DerivationKind::Generate);
+    assert_eq!(derivation.pattern, 1);
+    assert_eq!(derivation.text, "kum");
+
+    let children: Vec<(&DerivationKind, usize, &str)> = derivation.children.iter().map(|x| (&x.kind, x.pattern, x.text.as_str())).collect();
+    assert_eq!(children, vec![
+        (&DerivationKind::Variable(String::from("C")), 2, "k"),
+        (&DerivationKind::Variable(String::from("V")), 2, "u"),
+        (&DerivationKind::InnerPattern, 1, "m"),
+    ]);
+}
+
+#[test]
+fn accepts_recursive() {
+    let data = Zatlin::create_data(r#"
+    L = L "a" | "b";
+    R = "a" R | "b";
+
+    % L "-" R;
+    "#).unwrap();
+
+    assert!(data.accepts("baaa-aab"));
+    assert!(!data.accepts("aab-b"));
+}
+
+#[test]
+fn accepts_ambiguous() {
+    // every split of the word into "a" and "aa" is a derivation.
+    let data = Zatlin::create_data(r#"
+    V = "a" | "aa";
+    W = V V | V;
+
+    % V{0,20} | W{0,20} "b";
+    "#).unwrap();
+
+    assert!(data.accepts(&"a".repeat(40)));
+    assert!(!data.accepts(&"a".repeat(41)));
+    assert!(data.accepts(&format!("{}b", "a".repeat(80))));
+    assert!(!data.accepts(&format!("{}c", "a".repeat(80))));
+}
+
+#[test]
+fn analysis_not_supported() {
+    for text in [
+        r#"rewrite "p" -> "b"; % "pa" | "ta";"#,
+        r#"@syllable S; @nucleus V; @stress initial; V = "a"; S = "p" V; % S S;"#,
+        r#"@length chars {2}; % "pa" | "pan";"#,
+    ] {
+        let data = Zatlin::create_data(text).unwrap();
+
+        assert!(data.enumerate().is_err());
+        assert!(data.count_words().is_err());
+        assert!(data.probability("pa").is_err());
+        assert!(data.derive("pa").is_err());
+        assert!(!data.accepts("pa"));
+    }
+}
+
+#[test]
+fn generate_traced() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i" | "u";
+    S = C V | C V "n";
+
+    % S S | S ("-" S);
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(7);
+    for _ in 0..32 {
+        let (word, derivation) = zatlin.generate_traced(&data).unwrap();
+        assert_eq!(derivation.kind, DerivationKind::Generate);
+        assert_eq!(derivation.text, word);
+
+        let syllables: Vec<&str> = derivation.children.iter().map(|x| x.text.as_str()).collect();
+        assert_eq!(syllables.concat(), word);
+        assert!(derivation.children.iter().filter(|x| x.kind == DerivationKind::Variable(String::from("S"))).all(|x| {
+            let text: Vec<&str> = x.children.iter().map(|x| x.text.as_str()).collect();
+            x.text.starts_with(&text.concat()) && x.text.ends_with('n') == (x.pattern == 1)
+        }));
+    }
+}
+
+#[test]
+fn compiled_sampling() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k" | "s" | "m" | "n" | "l" | "r";
+    V = "a" | "i" | "u";
+
+    # only 3 of 576 words are not excluded.
+    % C V C V - ^ ("t" | "k" | "s" | "m" | "n" | "l" | "r") | ("i" | "u") ^ | ^ "p" ("i" | "u") | "a" ("t" | "k" | "s" | "m" | "n" | "l" | "r");
+    "#).unwrap();
+
+    let mut zatlin = Zatlin::with_seed(1);
+    zatlin.set_sampling_mode(SamplingMode::Compiled);
+
+    let result = zatlin.generate_many_by(&data, 3000);
+    assert!(result.iter().all(|x| x.is_ok()));
+
+    for word in ["papa", "pala", "para"] {
+        let frequency = result.iter().filter(|x| x.as_ref().unwrap() == word).count() as f64 / 3000.0;
+        assert!((frequency - data.probability(word).unwrap()).abs() < 0.05, "{}: {}", word, frequency);
+    }
+
+    // grammars which can not be compiled are sampled by rejection.
+    let data = Zatlin::create_data(r#"% ("a" | "b") ("a" | "b") - /\bb/;"#).unwrap();
+    assert!(zatlin.generate_many_by(&data, 50).into_iter().all(|x| x.unwrap().starts_with('a')));
+
+    let data = Zatlin::create_data(r#"S = "a" | S "a"; % S "b";"#).unwrap();
+    assert!(zatlin.generate_many_by(&data, 50).into_iter().all(|x| x.unwrap().ends_with("ab")));
+
+    let data = Zatlin::create_data(r#"% X;"#).unwrap();
+    assert_eq!(zatlin.generate_by(&data), Err(Error::NotFoundVariable(String::from("X"))));
+}
+
+#[test]
+fn compiled_sampling_same_distribution() {
+    let data = Zatlin::create_data(r#"
+    C = "k" 3 | "s";
+    V = "a" | "u";
+
+    % C V 3 | "ka" - "su" ^;
+    "#).unwrap();
+
+    let mut zatlin = Zatlin::with_seed(2);
+    zatlin.set_sampling_mode(SamplingMode::Compiled);
+    let compiled = zatlin.generate_many_by(&data, 4000);
+    let rejection = Zatlin::with_seed(3).generate_many_by(&data, 4000);
+
+    for word in ["ka", "ku", "sa"] {
+        let compiled = compiled.iter().filter(|x| x.as_ref().unwrap() == word).count() as f64 / 4000.0;
+        let rejection = rejection.iter().filter(|x| x.as_ref().unwrap() == word).count() as f64 / 4000.0;
+        assert!((compiled - rejection).abs() < 0.05, "{}: {} != {}", word, compiled, rejection);
+    }
+}
+
+#[test]
+fn generate_unique() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k" | "s" | "m" | "n";
+    V = "a" | "i" | "u";
+
+    % C V C V;
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(4);
+    let result = zatlin.generate_unique(&data, 100).unwrap();
+    assert_eq!(result.len(), 100);
+    assert_eq!(result.iter().collect::<std::collections::HashSet<_>>().len(), 100);
+
+    let result = zatlin.generate_unique(&data, 324).unwrap();
+    assert_eq!(result.len(), 324);
+    assert_eq!(result.iter().collect::<std::collections::HashSet<_>>().len(), 324);
+}
+
+#[test]
+fn generate_unique_exhausted() {
+    let zatlin = Zatlin::with_seed(5);
+
+    let data = Zatlin::create_data(r#"
+    % ("p" | "t" | "k") ("a" | "i") - ^ "ti";
+    "#).unwrap();
+    match zatlin.generate_unique(&data, 10) {
+        Err(Error::Exhausted(mut words)) => {
+            words.sort();
+            assert_eq!(words, vec!["ka", "ki", "pa", "pi", "ta"]);
+        },
+        other => panic!("{:?}", other),
+    }
+
+    // recursive grammars can not be counted, so generation stops after many duplicates.
+    let data = Zatlin::create_data(r#"
+    S = "a" | S "a";
+
+    % S - "aaa";
+    "#).unwrap();
+    match zatlin.generate_unique(&data, 3) {
+        Err(Error::Exhausted(mut words)) => {
+            words.sort();
+            assert_eq!(words, vec!["a", "aa"]);
+        },
+        other => panic!("{:?}", other),
+    }
+
+    // the rule prevents counting, so a rare word needs a higher miss limit.
+    let data = Zatlin::create_data(r#"
+    rewrite "x" -> "y";
+
+    % "a" 9999 | "b";
+    "#).unwrap();
+    let mut zatlin = Zatlin::with_seed(5);
+    zatlin.set_unique_miss_limit(1);
+    assert_eq!(zatlin.generate_unique(&data, 2), Err(Error::Exhausted(vec![String::from("a")])));
+
+    zatlin.set_unique_miss_limit(1_000_000);
+    let mut words = zatlin.generate_unique(&data, 2).unwrap();
+    words.sort();
+    assert_eq!(words, vec!["a", "b"]);
+}
+
+#[test]
+fn lexicon() {
+    let data = Zatlin::create_data(r#"
+    % ("p" | "t" | "k") ("a" | "i");
+    "#).unwrap();
+
+    let mut zatlin = Zatlin::with_seed(6);
+    zatlin.set_lexicon(Lexicon::new(["pa", "ti", "ka"]));
+    let result = zatlin.generate_unique(&data, 6);
+    match result {
+        Err(Error::Exhausted(mut words)) => {
+            words.sort();
+            assert_eq!(words, vec!["ki", "pi", "ta"]);
+        },
+        other => panic!("{:?}", other),
+    }
+
+    zatlin.set_sampling_mode(SamplingMode::Compiled);
+    let result = zatlin.generate_many_by(&data, 100);
+    assert!(result.iter().all(|x| ["ki", "pi", "ta"].contains(&x.as_ref().unwrap().as_str())));
+}
+
+#[test]
+fn lexicon_distance() {
+    let data = Zatlin::create_data(r#"
+    % ("p" | "t" | "k") ("a" | "i") ("n" | "");
+    "#).unwrap();
+
+    let mut lexicon = Lexicon::new(["pan", "tik"]);
+    lexicon.set_distance(1);
+    let mut zatlin = Zatlin::with_seed(7);
+    zatlin.set_lexicon(lexicon);
+
+    // "pan", "pa", "pin", "tan", "kan" and "ti", "tin" are too close.
+    let mut result = zatlin.generate_unique(&data, 5).unwrap();
+    result.sort();
+    assert_eq!(result, vec!["ka", "ki", "kin", "pi", "ta"]);
+}
+
+#[test]
+fn data_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<zatlin::Data>();
+
+    let data = std::sync::Arc::new(Zatlin::create_data(r#"
+    % ("p" | "t" | "k") ("a" | "i");
+    "#).unwrap());
+
+    let handles: Vec<_> = (0..4).map(|i| {
+        let data = std::sync::Arc::clone(&data);
+        std::thread::spawn(move || Zatlin::with_seed(i).generate_by(&data))
+    }).collect();
+    for handle in handles {
+        assert!(handle.join().unwrap().is_ok());
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_generate_many() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k" | "s" | "m" | "n";
+    V = "a" | "i" | "u";
+
+    % C V C V - ^ "s" | "n" ^;
+    "#).unwrap();
+
+    let result = Zatlin::with_seed(8).par_generate_many_by(&data, 1000);
+    assert_eq!(result.len(), 1000);
+    assert!(result.iter().all(|x| x.is_ok()));
+    assert_eq!(result, Zatlin::with_seed(8).par_generate_many_by(&data, 1000));
+}
+
+#[test]
+fn iter() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k" | "s" | "m" | "n";
+    V = "a" | "i" | "u";
+
+    % C V | C V C V;
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(9);
+    let result: Vec<String> = zatlin.iter(&data)
+        .filter_map(|x| x.ok())
+        .filter(|x| x.len() == 4)
+        .take(50)
+        .collect();
+    assert_eq!(result.len(), 50);
+    assert!(result.iter().all(|x| x.len() == 4));
+
+    let result: Vec<Result<String, Error>> = Zatlin::with_seed(10).iter(&data).take(20).collect();
+    assert_eq!(result, Zatlin::with_seed(10).generate_many_by(&data, 20));
+}
+
+#[test]
+fn generate_named() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i";
+
+    %noun = C V "n";
+    %verb = C V C V - ^ "t";
+    %verb = V C;
+    "#).unwrap();
+    assert_eq!(data.generate_names(), vec!["noun", "verb"]);
+
+    let zatlin = Zatlin::with_seed(11);
+    for _ in 0..50 {
+        let noun = zatlin.generate_named(&data, "noun").unwrap();
+        assert!(noun.len() == 3 && noun.ends_with('n'));
+
+        let verb = zatlin.generate_named(&data, "verb").unwrap();
+        assert!(verb.len() == 4 && !verb.starts_with('t') || verb.len() == 2);
+    }
+    assert_eq!(zatlin.generate_named(&data, "adjective"), Err(Error::NotFoundGenerate(String::from("adjective"))));
+
+    // every generate statement is used when no name is given.
+    assert_eq!(data.count_words().unwrap(), BigUint::from(6u32 + 24 + 6));
+}
+
+#[test]
+fn generate_weighted() {
+    let data = Zatlin::create_data(r#"
+    % 3 = "a";
+    % "b";
+    "#).unwrap();
+
+    assert_eq!(data.probability("a").unwrap(), 0.75);
+
+    let mut zatlin = Zatlin::with_seed(12);
+    let result = zatlin.generate_many_by(&data, 2000);
+    let frequency = result.iter().filter(|x| x.as_ref().unwrap() == "a").count() as f64 / 2000.0;
+    assert!((frequency - 0.75).abs() < 0.05);
+
+    zatlin.set_sampling_mode(SamplingMode::Compiled);
+    let result = zatlin.generate_many_by(&data, 2000);
+    let frequency = result.iter().filter(|x| x.as_ref().unwrap() == "a").count() as f64 / 2000.0;
+    assert!((frequency - 0.75).abs() < 0.05);
+}
+
+#[test]
+fn generate_variable() {
+    let data = Zatlin::create_data(r#"
+    Va = "a" | "á";
+    Vi = "i" | "í";
+    Vx = Va Vi | Vi Va - "íá";
+
+    % "p" Vx;
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(13);
+    for _ in 0..50 {
+        let result = zatlin.generate_variable(&data, "Vx").unwrap();
+        assert!(["ai", "aí", "ái", "áí", "ia", "iá", "ía"].contains(&result.as_str()));
+        assert!(["a", "á"].contains(&zatlin.generate_variable(&data, "Va").unwrap().as_str()));
+    }
+
+    assert_eq!(zatlin.generate_variable(&data, "Vu"), Err(Error::NotFoundVariable(String::from("Vu"))));
+
+    // an undefined variable inside the value is reported as soon as it is drawn.
+    let data = Zatlin::create_data(r#"X = "a" | Y;"#).unwrap();
+    let result: Vec<Result<String, Error>> = (0..20).map(|_| zatlin.generate_variable(&data, "X")).collect();
+    assert!(result.contains(&Err(Error::NotFoundVariable(String::from("Y")))));
+    assert!(result.iter().all(|x| x == &Ok(String::from("a")) || x == &Err(Error::NotFoundVariable(String::from("Y")))));
+}
+
+#[test]
+fn import() {
+    let data = zatlin::Data::read_file("tests/import/daughter.zatlin").unwrap();
+
+    // "ti" is excluded by the imported variable, and "C" of the daughter does not replace "Common.C".
+    assert_eq!(data.count_words().unwrap(), BigUint::from(8u32 * 2 + 9));
+    assert!(data.accepts("kus"));
+    assert!(data.accepts("ti"));
+    assert!(!data.accepts("tin"));
+    assert!(!data.accepts("sas"));
+
+    let data = zatlin::Data::read_file("tests/import/plain.zatlin").unwrap();
+    assert_eq!(data.count_words().unwrap(), BigUint::from(64u32));
+}
+
+#[test]
+fn import_error() {
+    match zatlin::Data::read_file("tests/import/cycle_a.zatlin") {
+        Err(Error::InFile(file, error)) => {
+            assert!(file.ends_with("cycle_b.zatlin"));
+            assert!(matches!(*error, Error::ErrorMessage(message, None) if message.starts_with("Import cycle")));
+        },
+        other => panic!("{:?}", other),
+    }
+
+    match zatlin::Data::read_file("tests/import/broken.zatlin") {
+        Err(Error::InFile(file, error)) => {
+            assert!(file.ends_with("invalid.zatlin"));
+            assert!(matches!(*error, Error::ErrorMessage(_, Some(_))));
+        },
+        other => panic!("{:?}", other),
+    }
+
+    let result = Zatlin::create_data(r#"
+    @import "not_found.zatlin";
+    % "a";
+    "#);
+    assert!(matches!(result, Err(Error::ErrorMessage(message, None)) if message.starts_with("file not found")));
+}
+
+#[test]
+fn quantifier() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "i";
+
+    % (C V){1,3} "n"? - "aa" | V{2};
+    "#).unwrap();
+
+    // 6 + 36 + 216 syllable sequences, each with or without "n".
+    assert_eq!(data.count_words().unwrap(), BigUint::from((6u32 + 36 + 216) * 2));
+    assert_eq!(data.probability("pa").unwrap(), 1.0 / 3.0 / 6.0 / 2.0);
+
+    let zatlin = Zatlin::with_seed(14);
+    for (word, derivation) in (0..100).map(|_| zatlin.generate_traced(&data).unwrap()) {
+        let repeat = &derivation.children[0];
+        assert_eq!(repeat.kind, DerivationKind::Repeat);
+        assert_eq!(repeat.children.len(), repeat.pattern);
+        assert_eq!(word.len(), repeat.pattern * 2 + derivation.children[1].pattern);
+        assert!(data.accepts(&word));
+    }
+
+    let derivation = data.derive("pakin").unwrap().unwrap();
+    assert_eq!(derivation.children[0].pattern, 2);
+    assert_eq!(derivation.children[1].pattern, 1);
+}
+
+#[test]
+fn quantifier_in_exclude() {
+    // quantifiers in excludes become regex quantifiers.
+    let data = Zatlin::create_data(r#"
+    V = "a" | "b" | "c";
+
+    % V{3} "d" - ^ V{0,12} "d";
+    "#).unwrap();
+    assert_eq!(data.count_words().unwrap(), BigUint::from(0u32));
+
+    let data = Zatlin::create_data(r#"% ("a" | "b") "c"{0,2} "b" - ^ "b" ("a" | "b" | "c"){0,12} ^;"#).unwrap();
+    assert!(data.accepts("acb") && !data.accepts("bcb"));
+
+    // values referred to by a backreference are expanded, up to a limit.
+    let result = Zatlin::create_data(r#"% "a" - ("a" | "b" | "c"){0,12} &1;"#);
+    assert!(matches!(result, Err(Error::ErrorMessage(_, None))), "{:?}", result);
+}
+
+#[test]
+fn regex_exclude() {
+    let data = Zatlin::create_data(r#"
+    C = "p" | "t" | "k";
+    V = "a" | "e" | "i" | "o" | "u";
+
+    % (C | V){4} - /[aeiou]{3}/ | ^ /[ptk]{2}/;
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(15);
+    for word in zatlin.generate_many_by(&data, 200).into_iter().map(|x| x.unwrap()) {
+        let vowels: Vec<bool> = word.chars().map(|x| "aeiou".contains(x)).collect();
+        assert!(!vowels.windows(3).any(|x| x.iter().all(|y| *y)));
+        assert!(vowels[0] || vowels[1]);
+    }
+    assert!(!data.accepts("paeu"));
+    assert!(data.accepts("paep"));
+
+    let result = Zatlin::create_data(r#"
+    % "a" - /(a/;
+    "#);
+    assert_eq!(result.unwrap_err(), Error::InvalidRegex(String::from("(a"), 2, 12));
+}
+
+#[test]
+fn rewrite() {
+    let data = Zatlin::create_data(r#"
+    rewrite "np" -> "mp";
+    rewrite "n" -> "ŋ" when _ /[kg]/;
+    rewrite /([aiu])h/ -> "$1$1" when "t" _;
+
+    % "a" ("n" | "m") ("p" | "k" | "t") "a" ("h" | "");
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(16);
+    for (word, derivation) in (0..100).map(|_| zatlin.generate_traced(&data).unwrap()) {
+        assert!(!word.contains("np") && !word.contains("nk") && !word.contains("tah"));
+        assert!(["ampa", "aŋka", "anta", "amka", "amta", "ampah", "aŋkah", "antaa", "amkah", "amtaa"].contains(&word.as_str()));
+        assert!(data.derive(&derivation.text).is_err());
+    }
+}
+
+#[test]
+fn rewrite_and_exclude() {
+    let early = Zatlin::create_data(r#"
+    rewrite "np" -> "mp";
+    % "a" ("n" | "m") "pa" - "mp";
+    "#).unwrap();
+    assert_eq!(Zatlin::with_seed(17).generate_by(&early), Err(Error::OverRetryCount));
+
+    let late = Zatlin::create_data(r#"
+    rewrite "np" -> "mp" after exclude;
+    % "a" ("n" | "m") "pa" - "mp";
+    "#).unwrap();
+    assert!(Zatlin::with_seed(17).generate_many_by(&late, 20).into_iter().all(|x| x.unwrap() == "ampa"));
+
+    // compiled sampling checks the excludes after the rewrite, too.
+    let data = Zatlin::create_data(r#"
+    rewrite "n" -> "m" when _ "p";
+    % ("n" | "m") ("p" | "t") 3 | "a" - "mp";
+    "#).unwrap();
+    let mut zatlin = Zatlin::with_seed(18);
+    zatlin.set_sampling_mode(SamplingMode::Compiled);
+    let result = zatlin.generate_many_by(&data, 2000);
+    assert!(result.iter().all(|x| ["nt", "mt", "a"].contains(&x.as_ref().unwrap().as_str())));
+
+    let frequency = result.iter().filter(|x| x.as_ref().unwrap() == "a").count() as f64 / 2000.0;
+    assert!((frequency - 0.4).abs() < 0.03, "{}", frequency);
+}
+
+#[test]
+fn map() {
+    let data = Zatlin::create_data(r#"
+    map latin {
+        "ʃ" -> "sh";
+        "t" -> "t";
+        "tʃ" -> "ch";
+    }
+    map kana { "ʃa" -> "しゃ"; "ta" -> "た"; "tʃa" -> "ちゃ"; }
+
+    % ("ʃ" | "t" | "tʃ") "a";
+    "#).unwrap();
+    assert_eq!(data.map_names(), vec!["latin", "kana"]);
+    assert_eq!(data.render("tʃaʃat", "latin"), Ok(String::from("chashat")));
+    assert_eq!(data.render("tʃaʃat", "kana"), Ok(String::from("ちゃしゃt")));
+    assert_eq!(data.render("ta", "cyrillic"), Err(Error::NotFoundMap(String::from("cyrillic"))));
+
+    let zatlin = Zatlin::with_seed(19);
+    for _ in 0..20 {
+        let (word, renderings) = zatlin.generate_rendered(&data, &["kana", "latin"]).unwrap();
+        let expected = match word.as_str() {
+            "ʃa" => ["しゃ", "sha"],
+            "ta" => ["た", "ta"],
+            "tʃa" => ["ちゃ", "cha"],
+            _ => unreachable!(),
+        };
+        assert_eq!(renderings, expected);
+    }
+
+    assert_eq!(zatlin.generate_rendered(&data, &["latin", "cyrillic"]), Err(Error::NotFoundMap(String::from("cyrillic"))));
+}
+
+#[test]
+fn syllables() {
+    let data = Zatlin::create_data(r#"
+    @syllable S Sf;
+    @onset Cs;
+    @nucleus V;
+    @coda Ce;
+
+    Cs = "p" | "tr";
+    V = "a" | "ai";
+    Ce = "n" | "";
+    S = Cs V;
+    Sf = Cs V Ce;
+
+    % S S Sf "-s";
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(20);
+    for _ in 0..20 {
+        let word = zatlin.generate_syllables(&data).unwrap();
+        assert_eq!(word.syllables.len(), 3);
+
+        let text: String = word.syllables.iter().map(|x| x.text.as_str()).collect();
+        assert_eq!(format!("{}-s", text), word.text);
+        for syllable in word.syllables.iter() {
+            assert_eq!(format!("{}{}{}", syllable.onset, syllable.nucleus, syllable.coda), syllable.text);
+        }
+        assert!(word.syllables[..2].iter().all(|x| x.coda.is_empty()));
+    }
+
+    let derivation = data.derive("trapaitran-s").unwrap().unwrap();
+    assert_eq!(data.syllabify(&derivation), vec![
+        Syllable { text: String::from("tra"), onset: String::from("tr"), nucleus: String::from("a"), coda: String::new() },
+        Syllable { text: String::from("pai"), onset: String::from("p"), nucleus: String::from("ai"), coda: String::new() },
+        Syllable { text: String::from("tran"), onset: String::from("tr"), nucleus: String::from("a"), coda: String::from("n") },
+    ]);
+}
+
+#[test]
+fn stress() {
+    let data = Zatlin::create_data(r#"
+    @syllable S;
+    @onset C;
+    @nucleus V;
+    @coda Ce;
+    @stress final heavy | penult;
+
+    C = "p" | "t";
+    V = "a" | "i";
+    Ce = "n" | "";
+    S = C V Ce;
+
+    % S | S S | S S S;
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(21);
+    for _ in 0..50 {
+        let word = zatlin.generate_syllables(&data).unwrap();
+        let length = word.syllables.len();
+        let expected = if word.syllables[length - 1].is_heavy() { length - 1 } else { length.saturating_sub(2) };
+        assert_eq!(word.stress, Some(expected));
+
+        let marked: Vec<String> = word.syllables.iter().enumerate()
+            .map(|(index, x)| if index == expected { format!("ˈ{}", x.text) } else { x.text.clone() })
+            .collect();
+        assert_eq!(word.text, marked.concat());
+    }
+
+    // compiled sampling falls back to rejection sampling to place the stress.
+    let mut zatlin = Zatlin::with_seed(21);
+    zatlin.set_sampling_mode(SamplingMode::Compiled);
+    assert!(zatlin.generate_many_by(&data, 20).into_iter().all(|x| x.unwrap().contains('ˈ')));
+}
+
+#[test]
+fn stress_in_excludes() {
+    let data = Zatlin::create_data(&format!(r#"
+    @syllable S;
+    @nucleus V;
+    @stress final heavy | penult as acute;
+
+    V = "a" | "aa";
+    S = ("p" | "t") V;
+
+    % S S - "{}" V ^;
+    "#, '\u{301}')).unwrap();
+
+    // a heavy final syllable would be stressed, which is excluded.
+    let zatlin = Zatlin::with_seed(22);
+    for word in zatlin.generate_many_by(&data, 50).into_iter().map(|x| x.unwrap()) {
+        assert!(!word.ends_with("aa"), "{}", word);
+        assert_eq!(word.chars().nth(2), Some('\u{301}'), "{}", word);
+    }
+}
+
+#[test]
+fn stress_with_length() {
+    for length in ["chars", "segments"] {
+        let data = Zatlin::create_data(&format!(r#"
+        @syllable S;
+        @nucleus V;
+        @stress initial;
+        @length {} {{4}};
+
+        V = "a";
+        S = "p" V;
+
+        % S S;
+        "#, length)).unwrap();
+
+        // the stress mark does not count toward the length.
+        let zatlin = Zatlin::with_seed(23);
+        assert_eq!(zatlin.generate_by(&data).unwrap(), "ˈpapa", "{}", length);
+    }
+
+    let data = Zatlin::create_data(r#"
+    @syllable S;
+    @nucleus V;
+    @stress initial as acute;
+    @length chars {4};
+
+    V = "a";
+    S = "p" V;
+
+    % S S;
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(23);
+    assert_eq!(zatlin.generate_by(&data).unwrap(), "pa\u{301}pa");
+}
+
+#[test]
+fn distribution() {
+    let data = Zatlin::create_data(r#"
+    C = @zipf(1) "p" | "t" | "k";
+    V = @geometric(0.5) "a" | "i" 2;
+    % C V;
+    "#).unwrap();
+
+    let total_c = 1.0 + 1.0 / 2.0 + 1.0 / 3.0;
+    let total_v = 0.5 + 0.25 * 2.0;
+    assert!((data.probability("pa").unwrap() - (1.0 / total_c) * (0.5 / total_v)).abs() < 1e-9);
+    assert!((data.probability("ki").unwrap() - (1.0 / 3.0 / total_c) * (0.5 / total_v)).abs() < 1e-9);
+
+    let zatlin = Zatlin::with_seed(23);
+    let result = zatlin.generate_many_by(&data, 3000);
+    let frequency = result.iter().filter(|x| x.as_ref().unwrap().starts_with('p')).count() as f64 / 3000.0;
+    assert!((frequency - 1.0 / total_c).abs() < 0.03, "{}", frequency);
+}
+
+#[test]
+fn length() {
+    let data = Zatlin::create_data(r#"
+    @syllable S;
+    @length syllables {2,3};
+    @length chars {,7};
+
+    S = ("p" | "tr") ("a" | "ai");
+    % S{1,5};
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(24);
+    for _ in 0..50 {
+        let word = zatlin.generate_syllables(&data).unwrap();
+        assert!((2..=3).contains(&word.syllables.len()), "{:?}", word);
+        assert!(word.text.chars().count() <= 7, "{:?}", word);
+    }
+
+    // a combining accent is a part of the grapheme before it.
+    let data = Zatlin::create_data(&format!(r#"
+    @length graphemes {{2}};
+    % ("e" | "é" | "e{0}") ("e" | "é" | "e{0}") ("" | "e");
+    "#, '\u{301}')).unwrap();
+    let mut zatlin = Zatlin::with_seed(25);
+    zatlin.set_sampling_mode(SamplingMode::Compiled);
+    for word in zatlin.generate_many_by(&data, 30).into_iter().map(|x| x.unwrap()) {
+        assert_eq!(word.chars().filter(|x| *x != '\u{301}').count(), 2, "{}", word);
+    }
+}
+
+#[test]
+fn length_unsatisfiable() {
+    let unsatisfiable = |text: &str| match Zatlin::create_data(text) {
+        Err(Error::Unsatisfiable(constraint)) => constraint,
+        result => panic!("{:?}", result.map(|_| ())),
+    };
+
+    assert_eq!(unsatisfiable(r#"@length chars {4}; % ("a" | "bb") ("c" | "ddddd");"#), "@length chars {4,4}");
+    assert_eq!(unsatisfiable(r#"@length graphemes {3,}; % "a" | "bb";"#), "@length graphemes {3,}");
+    assert_eq!(unsatisfiable(r#"@syllable S; @length syllables {4,}; S = "pa"; % S S | S{1,3};"#), "@length syllables {4,}");
+
+    // excludes are not known to the syllable count, so this is only found by generating.
+    let data = Zatlin::create_data(r#"@syllable S; @length syllables {2}; S = "pa"; % S | S S - "papa";"#).unwrap();
+    assert_eq!(Zatlin::default().generate_by(&data), Err(Error::OverRetryCount));
+
+    // recursive grammars have no upper bound.
+    assert!(Zatlin::create_data(r#"@syllable S; @length syllables {4,}; S = "pa"; W = S | S W; % W;"#).is_ok());
+    assert!(Zatlin::create_data(r#"@length chars {3}; % ("a" | "bb") ("c" | "dd");"#).is_ok());
+}
+
+#[test]
+fn segments() {
+    let data = Zatlin::create_data(r#"
+    @segments "sh" "ts" "aa";
+    @length segments {4};
+
+    C = "s" | "sh" | "t" | "ts";
+    V = "a" | "aa";
+    % C V &1 ("" | V) - ^ "s" | /a$/;
+    "#).unwrap();
+
+    let zatlin = Zatlin::with_seed(26);
+    let mut starts_with_sh = false;
+    for _ in 0..50 {
+        let (word, derivation) = zatlin.generate_traced(&data).unwrap();
+        assert_eq!(derivation.text, word);
+        for child in derivation.children.iter() {
+            assert_eq!(&word[child.offset..child.offset + child.text.len()], child.text);
+        }
+
+        // "sh" is one segment, so it is neither excluded by `^ "s"` nor split by the backreference.
+        let first = derivation.children[0].text.as_str();
+        assert!(first != "s" && word.starts_with(first));
+        assert!(word.trim_end_matches('a').ends_with(first), "{}", word);
+        assert!(word.ends_with("aa"), "{}", word);
+        starts_with_sh |= first == "sh";
+    }
+    assert!(starts_with_sh);
+
+    // the analyses do not apply the length constraint.
+    assert!(data.enumerate().is_err() && data.derive("shashaa").is_err());
+
+    let data = Zatlin::create_data(r#"@segments "sh" "ts" "aa"; C = "s" | "sh" | "t" | "ts"; % C "aa" &1 "aa" - ^ "s";"#).unwrap();
+    let words: Vec<String> = data.enumerate().unwrap().collect();
+    assert_eq!(words.len(), 3);
+    assert!(words.contains(&String::from("tsaatsaa")) && words.contains(&String::from("shaashaa")));
+    assert!(data.accepts("shaashaa") && !data.accepts("saasaa"));
+    assert!(data.probability("taataa").unwrap() > 0.0);
+
+    // rewrite rules see "sh" as one segment, too.
+    let data = Zatlin::create_data(r#"@segments "sh"; rewrite "s" -> "z"; % "sh" | "s" "h";"#).unwrap();
+    assert!(zatlin.generate_many_by(&data, 20).into_iter().all(|x| ["sh", "zh"].contains(&x.unwrap().as_str())));
+
+    // a literal target or context is encoded before its metacharacters are escaped.
+    let data = Zatlin::create_data(r#"@segments "t.s"; rewrite "t.s" -> "c" when _ "a"; % "t.s" "a";"#).unwrap();
+    assert_eq!(zatlin.generate_by(&data).unwrap(), "ca");
+}
+
+#[test]
+fn normalization() {
+    // the literal is decomposed and the exclude precomposed.
+    let text = "V = \"a\" | \"a\u{301}\"; % \"t\" V - \"t\u{e1}\";";
+
+    let zatlin = Zatlin::with_seed(27);
+    let data = Data::try_from(text).unwrap();
+    assert!(zatlin.generate_many_by(&data, 30).into_iter().any(|x| x.unwrap() == "ta\u{301}"));
+
+    for normalization in [Normalization::Nfc, Normalization::Nfd] {
+        let data = Data::with_normalization(text, normalization).unwrap();
+        assert!(zatlin.generate_many_by(&data, 30).into_iter().all(|x| x.unwrap() == "ta"));
+    }
+
+    // generated words are normalized, too.
+    let text = "@syllable S; @nucleus V; @stress initial as acute; V = \"e\" | \"o\"; S = \"t\" V; % S;";
+    let data = Data::with_normalization(text, Normalization::Nfc).unwrap();
+    assert!(zatlin.generate_many_by(&data, 10).into_iter().all(|x| ["t\u{e9}", "t\u{f3}"].contains(&x.unwrap().as_str())));
+
+    // a class of precomposed characters would match any base character once decomposed.
+    let text = "% \"t\" (\"a\" | \"e\" | \"\u{e1}\") - /[\u{e1}\u{e9}]/;";
+    assert!(matches!(Data::with_normalization(text, Normalization::Nfd), Err(Error::InvalidRegex(..))));
+    let data = Data::with_normalization(text, Normalization::Nfc).unwrap();
+    assert!(zatlin.generate_many_by(&data, 20).into_iter().all(|x| ["ta", "te"].contains(&x.unwrap().as_str())));
+
+    let text = "% \"t\" (\"a\" | \"e\" | \"\u{e1}\") - /t\u{e1}/;";
+    let data = Data::with_normalization(text, Normalization::Nfd).unwrap();
+    assert!(zatlin.generate_many_by(&data, 20).into_iter().all(|x| ["ta", "te"].contains(&x.unwrap().as_str())));
+
+    // the analyses do not apply normalization, which can merge words and change what the excludes match.
+    let text = "% \"a\" (\"\u{301}\" | \"\") | \"\u{e1}\";";
+    let data = Data::with_normalization(text, Normalization::Nfc).unwrap();
+    assert!(data.enumerate().is_err() && data.count_words().is_err());
+    assert!(data.probability("\u{e1}").is_err() && data.derive("\u{e1}").is_err());
+    let mut words = zatlin.generate_unique(&data, 2).unwrap();
+    words.sort();
+    assert_eq!(words, vec!["a", "\u{e1}"]);
+    assert!(matches!(zatlin.generate_unique(&data, 3), Err(Error::Exhausted(words)) if words.len() == 2));
+
+    // the lexicon matches either spelling.
+    let text = "% \"t\" (\"a\" | \"\u{e1}\");";
+    for normalization in [Normalization::None, Normalization::Nfc, Normalization::Nfd] {
+        let data = Data::with_normalization(text, normalization).unwrap();
+        let mut zatlin = Zatlin::with_seed(28);
+        zatlin.set_lexicon(Lexicon::new(["ta\u{301}"]));
+        assert!(zatlin.generate_many_by(&data, 20).into_iter().all(|x| x.unwrap() == "ta"));
+    }
+    assert!(Lexicon::new(["t\u{e1}"]).contains("ta\u{301}"));
+}